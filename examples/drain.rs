@@ -1,5 +1,9 @@
+use std::time::Duration;
+use std::time::Instant;
 use xilinx_dma::AxiDma;
+use xilinx_dma::D2hStreamer;
 use xilinx_dma::DmaBuffer;
+use xilinx_dma::DmaBufferPool;
 use xilinx_dma::Error;
 
 fn main() -> Result<(), Error> {
@@ -13,12 +17,19 @@ fn main() -> Result<(), Error> {
     dma_h2d.reset();
     dma_d2h.reset();
 
-    dma_d2h.start_d2h(&dma_buffer, dma_buffer.size())?;
-    std::thread::sleep(std::time::Duration::from_secs_f64(0.1));
-    dma_d2h.start_d2h(&dma_buffer, dma_buffer.size())?;
-    std::thread::sleep(std::time::Duration::from_secs_f64(0.1));
-    dma_d2h.start_d2h(&dma_buffer, dma_buffer.size())?;
-    std::thread::sleep(std::time::Duration::from_secs_f64(0.1));
+    // Use both halves of the buffer as ping-pong buffers.
+    let chunk = dma_buffer.size() / 2;
+    let pool = DmaBufferPool::from_chunks(dma_buffer, chunk);
+    let (streamer, chunks) = D2hStreamer::start(dma_d2h, pool, chunk);
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs_f64(0.3) {
+        if let Ok(chunk) = chunks.recv_timeout(Duration::from_millis(10)) {
+            println!("drained {} bytes", chunk.len());
+        }
+    }
+    println!("{:?}", streamer.stats());
+    let dma_d2h = streamer.stop()?;
 
     dma_h2d.status_h2d();
     dma_d2h.status_d2h();
@@ -36,21 +36,16 @@
 //! udmabuf_h2d0 {
 //!     compatible = "ikwzm,u-dma-buf";
 //!     size = <0x800000>;
-//!     sync-mode = <2>;
-//!     sync-always;
 //! };
 //!
 //! udmabuf_h2d1 {
 //!     compatible = "ikwzm,u-dma-buf";
 //!     size = <0x800000>;
-//!     sync-mode = <2>;
-//!     sync-always;
 //! };
 //!
 //! udmabuf_d2h0 {
 //!     compatible = "ikwzm,u-dma-buf";
 //!     size = <0x800000>;
-//!     sync-mode = <2>;
 //!     sync-direction = <2>;
 //!     sync-offset = <0x0>;
 //!     sync-size = <0x800000>;
@@ -59,7 +54,6 @@
 //! udmabuf_d2h1 {
 //!     compatible = "ikwzm,u-dma-buf";
 //!     size = <0x800000>;
-//!     sync-mode = <2>;
 //!     sync-direction = <2>;
 //!     sync-offset = <0x0>;
 //!     sync-size = <0x800000>;
@@ -75,28 +69,30 @@
 //! write-combining. This gives good throughput, because in this example the
 //! buffers are filled sequentially. The D2H buffers use cached memory with
 //! manual cache invalidation, since reading an uncached buffer sequentially to
-//! check its contents is very slow.
+//! check its contents is very slow. The mapping mode is selected when opening
+//! the buffers.
 
 use std::convert::TryFrom;
 use xilinx_dma::AxiDma;
 use xilinx_dma::DmaBuffer;
 use xilinx_dma::Error;
+use xilinx_dma::MappingMode;
 use xilinx_dma::SgDescriptor;
 use xilinx_dma::SG_DESCRIPTOR_LEN;
 
 fn main() -> Result<(), Error> {
     let descriptor_buffer = DmaBuffer::new("udmabuf_descriptors")?;
-    let mut h2d0 = DmaBuffer::new("udmabuf_h2d0")?;
-    let mut h2d1 = DmaBuffer::new("udmabuf_h2d1")?;
-    let mut d2h0 = DmaBuffer::new("udmabuf_d2h0")?;
-    let mut d2h1 = DmaBuffer::new("udmabuf_d2h1")?;
+    let mut h2d0 = DmaBuffer::open_with("udmabuf_h2d0", MappingMode::WriteCombine)?;
+    let mut h2d1 = DmaBuffer::open_with("udmabuf_h2d1", MappingMode::WriteCombine)?;
+    let mut d2h0 = DmaBuffer::open_with("udmabuf_d2h0", MappingMode::Cached)?;
+    let mut d2h1 = DmaBuffer::open_with("udmabuf_d2h1", MappingMode::Cached)?;
     let mut h2d_dma = AxiDma::new("uio0")?;
     let mut d2h_dma = AxiDma::new("uio1")?;
 
     // Set up descriptors
 
     // Create 4 descriptors
-    let descriptors_base_virt = descriptor_buffer.slice::<u32>().as_mut_ptr();
+    let descriptors_base_virt = descriptor_buffer.buffer() as *mut u32;
     let descriptors_base_phys = descriptor_buffer.phys_addr();
     let mut descriptors = (0..4)
         .map(|j| unsafe {
@@ -158,7 +154,7 @@ fn main() -> Result<(), Error> {
                 usize::try_from(transferred_bytes).unwrap() / std::mem::size_of::<u32>();
             // Invalidate cache of D2H buffer.
             current.1.sync_for_cpu()?;
-            checker.check_buffer(&current.1.slice::<u32>()[..transferred_items]);
+            checker.check_buffer(&current.1.as_slice::<u32>()[..transferred_items]);
             remaining -= u64::from(transferred_bytes);
             if remaining == 0 {
                 break;
@@ -217,8 +213,8 @@ impl DataGenerator {
         DataGenerator::default()
     }
 
-    fn fill_buffer(&mut self, buffer: &DmaBuffer) {
-        for x in buffer.slice::<u32>() {
+    fn fill_buffer(&mut self, buffer: &mut DmaBuffer) {
+        for x in buffer.as_mut_slice::<u32>() {
             *x = self.counter;
             self.counter = self.counter.wrapping_add(1);
         }
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::fs::File;
 use std::fs::OpenOptions;
@@ -5,17 +6,49 @@ use std::io::prelude::*;
 use std::os::fd::RawFd;
 use std::os::unix::io::AsRawFd;
 use std::ptr;
+use std::time::Duration;
 
 use crate::dmb;
-use crate::DmaBuffer;
+use crate::lock::lock_exclusive;
+use crate::DmaMemory;
 use crate::Error;
 #[cfg(feature = "scatter-gather")]
 use crate::SgDescriptor;
+#[cfg(feature = "scatter-gather")]
+use crate::SgRing;
+use crate::SysRoot;
+use crate::UioDevice;
 
-#[cfg(feature = "async")]
+#[cfg(any(feature = "async", feature = "tokio"))]
 mod axi_dma_async;
-#[cfg(feature = "async")]
-pub use axi_dma_async::AxiDmaAsync;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use axi_dma_async::{AsyncTransfer, AxiDmaAsync};
+mod d2h_streamer;
+pub use d2h_streamer::{D2hChunk, D2hStats, D2hStreamer};
+mod h2d_streamer;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use h2d_streamer::AsyncH2dStreamer;
+pub use h2d_streamer::{H2dStats, H2dStreamer};
+#[cfg(any(feature = "async", feature = "tokio"))]
+mod d2h_stream;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use d2h_stream::{D2hBuffer, D2hStream};
+#[cfg(any(feature = "async", feature = "tokio"))]
+mod h2d_sink;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use h2d_sink::{H2dSink, H2dSlot};
+mod d2h_reader;
+#[cfg(any(feature = "async", feature = "tokio"))]
+mod slots;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use d2h_reader::AsyncD2hReader;
+pub use d2h_reader::D2hReader;
+mod h2d_writer;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use h2d_writer::AsyncH2dWriter;
+pub use h2d_writer::H2dWriter;
+#[cfg(test)]
+mod fake;
 
 #[allow(clippy::erasing_op)]
 const MM2S_DMACR: isize = 0x0 / 4;
@@ -46,9 +79,158 @@ const S2MM_DA: isize = 0x48 / 4;
 const S2MM_DA_MSB: isize = 0x4C / 4;
 const S2MM_LENGTH: isize = 0x58 / 4;
 
+/// Scatter gather registers of a channel.
+#[cfg(feature = "scatter-gather")]
+struct SgRegisters {
+    dmacr: isize,
+    dmasr: isize,
+    currdesc: isize,
+    currdesc_msb: isize,
+    taildesc: isize,
+    taildesc_msb: isize,
+}
+
+#[cfg(feature = "scatter-gather")]
+const MM2S_SG: SgRegisters = SgRegisters {
+    dmacr: MM2S_DMACR,
+    dmasr: MM2S_DMASR,
+    currdesc: MM2S_CURRDESC,
+    currdesc_msb: MM2S_CURRDESC_MSB,
+    taildesc: MM2S_TAILDESC,
+    taildesc_msb: MM2S_TAILDESC_MSB,
+};
+
+#[cfg(feature = "scatter-gather")]
+const S2MM_SG: SgRegisters = SgRegisters {
+    dmacr: S2MM_DMACR,
+    dmasr: S2MM_DMASR,
+    currdesc: S2MM_CURRDESC,
+    currdesc_msb: S2MM_CURRDESC_MSB,
+    taildesc: S2MM_TAILDESC,
+    taildesc_msb: S2MM_TAILDESC_MSB,
+};
+
+#[cfg(feature = "scatter-gather")]
+impl SgRegisters {
+    /// Registers of the channel in the other direction.
+    fn other(&self) -> &'static SgRegisters {
+        if self.dmasr == MM2S_DMASR {
+            &S2MM_SG
+        } else {
+            &MM2S_SG
+        }
+    }
+}
+
+/// How [`AxiDma`] waits for transfers to complete.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompletionMode {
+    /// Block on the UIO interrupt.
+    #[default]
+    Interrupt,
+    /// Busy-poll DMASR (register mode) or the descriptor STATUS (scatter
+    /// gather) for at most `spin_budget` iterations. Once the budget is
+    /// exhausted, fall back to the UIO interrupt if `irq_fallback` is set or
+    /// fail with [`Error::Timeout`] otherwise.
+    Poll {
+        spin_budget: u64,
+        irq_fallback: bool,
+    },
+}
+
 pub struct AxiDma {
     dev_fd: File,
     dma: AxiDmaBase,
+    mode: CompletionMode,
+}
+
+/// How long dropping an unfinished [`Transfer`] waits for its completion.
+const DROP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A register mode transfer in flight.
+///
+/// The transfer borrows the buffer until it is completed, so it cannot be
+/// accessed while the DMA is using it. Dropping an unfinished transfer waits
+/// up to one second for its completion and aborts it if it does not
+/// complete, see [`Transfer::abort`].
+#[must_use = "dropping a transfer blocks until it is completed"]
+#[derive(Debug)]
+pub struct Transfer<'a, B> {
+    dma: &'a mut AxiDma,
+    _buffer: B,
+    channel: Channel,
+    done: bool,
+}
+
+impl<'a, B> Transfer<'a, B> {
+    fn new(dma: &'a mut AxiDma, buffer: B, channel: Channel) -> Transfer<'a, B> {
+        Transfer {
+            dma,
+            _buffer: buffer,
+            channel,
+            done: false,
+        }
+    }
+
+    /// Wait for the transfer to complete.
+    pub fn wait(mut self) -> Result<(), Error> {
+        self.done = true;
+        self.wait_channel()
+    }
+
+    /// Abort the transfer by resetting the DMA, unless it has already
+    /// completed. An interrupt raised by the transfer is discarded.
+    ///
+    /// The reset halts both channels, so a transfer in the other direction
+    /// is aborted as well.
+    pub fn abort(mut self) {
+        self.done = true;
+        self.abort_channel();
+    }
+
+    fn abort_channel(&mut self) {
+        if self.dma.dma.busy(self.channel.dmasr()) {
+            self.dma.reset();
+        }
+        let _ = self.dma.discard_irq(self.channel);
+    }
+
+    fn wait_channel(&mut self) -> Result<(), Error> {
+        match self.channel {
+            Channel::H2d => self.dma.wait_h2d(),
+            Channel::D2h => self.dma.wait_d2h(),
+        }
+    }
+}
+
+impl<B> Drop for Transfer<'_, B> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let completed = match self.channel {
+            Channel::H2d => self.dma.wait_h2d_timeout(DROP_TIMEOUT),
+            Channel::D2h => self.dma.wait_d2h_timeout(DROP_TIMEOUT),
+        };
+        if !matches!(completed, Ok(true)) {
+            self.abort_channel();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Channel {
+    H2d,
+    D2h,
+}
+
+impl Channel {
+    fn dmasr(self) -> isize {
+        match self {
+            Channel::H2d => MM2S_DMASR,
+            Channel::D2h => S2MM_DMASR,
+        }
+    }
 }
 
 struct AxiDmaBase {
@@ -62,39 +244,227 @@ impl fmt::Debug for AxiDma {
         writeln!(f, "AxiDma ({})", &self.dma.dev)?;
         writeln!(f, "  file: {:?}", &self.dev_fd)?;
         writeln!(f, "  base: {:?}", &self.dma.base)?;
-        write!(f, "  size: {:#x?}", &self.dma.size)
+        writeln!(f, "  size: {:#x?}", &self.dma.size)?;
+        write!(f, "  mode: {:?}", &self.mode)
+    }
+}
+
+/// Options for opening an [`AxiDma`] or [`AxiDmaAsync`].
+#[derive(Clone, Debug)]
+pub struct AxiDmaOptions {
+    root: SysRoot,
+    lock: bool,
+}
+
+impl AxiDmaOptions {
+    pub fn new() -> AxiDmaOptions {
+        AxiDmaOptions {
+            root: SysRoot::default(),
+            lock: true,
+        }
+    }
+
+    pub fn root(mut self, root: SysRoot) -> AxiDmaOptions {
+        self.root = root;
+        self
+    }
+
+    /// Take an exclusive advisory lock on the UIO device (default), failing
+    /// with [`Error::Busy`] if the DMA is already open with a lock. This
+    /// includes another handle to the same device in this process.
+    pub fn lock(mut self, lock: bool) -> AxiDmaOptions {
+        self.lock = lock;
+        self
+    }
+
+    /// Find the UIO device whose device-tree name is `name` under the root of
+    /// these options, e.g., to pass its [`uio`](UioDevice::uio) to
+    /// [`open`](Self::open).
+    pub fn find_by_name(&self, name: &str) -> Result<UioDevice, Error> {
+        UioDevice::find_by_name(&self.root, name)
+    }
+
+    /// Find the UIO device whose registers are at physical address `addr`
+    /// under the root of these options.
+    pub fn find_by_addr(&self, addr: usize) -> Result<UioDevice, Error> {
+        UioDevice::find_by_addr(&self.root, addr)
+    }
+
+    /// List the UIO devices under the root of these options.
+    pub fn list_devices(&self) -> Result<Vec<UioDevice>, Error> {
+        UioDevice::list(&self.root)
+    }
+
+    pub fn open(&self, uio: &str) -> Result<AxiDma, Error> {
+        AxiDma::open(uio, self)
+    }
+
+    /// Open the DMA with async-io.
+    #[cfg(feature = "async")]
+    pub fn open_async(&self, uio: &str) -> Result<AxiDmaAsync, Error> {
+        AxiDmaAsync::open_async(uio, self)
+    }
+
+    /// Open the DMA with the reactor of the current tokio runtime.
+    ///
+    /// # Panics
+    /// If called outside of a tokio runtime with I/O enabled.
+    #[cfg(feature = "tokio")]
+    pub fn open_tokio(&self, uio: &str) -> Result<AxiDmaAsync, Error> {
+        AxiDmaAsync::open_tokio(uio, self)
+    }
+
+    fn open_device(&self, uio: &str, options: &mut OpenOptions) -> Result<File, Error> {
+        let path = self.root.device(uio);
+        let dev_fd = options.read(true).write(true).open(&path)?;
+        if self.lock {
+            lock_exclusive(&dev_fd, &path)?;
+        }
+        Ok(dev_fd)
+    }
+}
+
+impl Default for AxiDmaOptions {
+    fn default() -> AxiDmaOptions {
+        AxiDmaOptions::new()
     }
 }
 
 impl AxiDma {
     pub fn new(uio: &str) -> Result<AxiDma, Error> {
-        let dev_fd = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(format!("/dev/{}", uio))?;
-        let dma = AxiDmaBase::new(uio, dev_fd.as_raw_fd())?;
-        Ok(AxiDma { dev_fd, dma })
-    }
-
-    pub fn start_h2d(&mut self, buff: &DmaBuffer, bytes: usize) -> Result<(), Error> {
-        self.dma.start_h2d_ini(buff, bytes);
-        self.enable_uio_irqs()?;
-        self.dma.start_h2d_fini(buff, bytes);
+        AxiDmaOptions::new().open(uio)
+    }
+
+    /// Open the DMA with sysfs and device nodes located under `root`.
+    pub fn with_root(uio: &str, root: &SysRoot) -> Result<AxiDma, Error> {
+        AxiDmaOptions::new().root(root.clone()).open(uio)
+    }
+
+    /// Open the DMA whose device-tree name is `name`, e.g., `h2d-dma`.
+    ///
+    /// Use [`AxiDmaOptions::find_by_name`] for other roots or options.
+    pub fn find_by_name(name: &str) -> Result<AxiDma, Error> {
+        let options = AxiDmaOptions::new();
+        options.open(options.find_by_name(name)?.uio())
+    }
+
+    /// Open the DMA whose registers are at physical address `addr`.
+    pub fn find_by_addr(addr: usize) -> Result<AxiDma, Error> {
+        let options = AxiDmaOptions::new();
+        options.open(options.find_by_addr(addr)?.uio())
+    }
+
+    /// List the UIO devices of the system.
+    pub fn list_devices() -> Result<Vec<UioDevice>, Error> {
+        AxiDmaOptions::new().list_devices()
+    }
+
+    fn open(uio: &str, options: &AxiDmaOptions) -> Result<AxiDma, Error> {
+        let dev_fd = options.open_device(uio, &mut OpenOptions::new())?;
+        let dma = AxiDmaBase::new(uio, &options.root, dev_fd.as_raw_fd())?;
+        Ok(AxiDma {
+            dev_fd,
+            dma,
+            mode: CompletionMode::Interrupt,
+        })
+    }
+
+    pub fn completion_mode(&self) -> CompletionMode {
+        self.mode
+    }
+
+    /// Select how transfers are waited for. In polling mode, the interrupt is
+    /// disabled on the UIO device, so that no events pile up while spinning.
+    pub fn set_completion_mode(&mut self, mode: CompletionMode) -> Result<(), Error> {
+        if let CompletionMode::Poll { .. } = mode {
+            self.disable_uio_irqs()?;
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Start a transfer. The buffer stays borrowed until the returned
+    /// [`Transfer`] is completed.
+    ///
+    /// Fails with [`Error::OutOfBounds`] if `bytes` exceeds the buffer.
+    pub fn start_h2d<'a, 'b, M: DmaMemory + ?Sized>(
+        &'a mut self,
+        buff: &'b M,
+        bytes: usize,
+    ) -> Result<Transfer<'a, &'b M>, Error> {
+        self.start_h2d_at(buff.phys_addr(), buff.len(), bytes)?;
+        Ok(Transfer::new(self, buff, Channel::H2d))
+    }
+
+    fn start_h2d_at(&mut self, phys_addr: usize, len: usize, bytes: usize) -> Result<(), Error> {
+        self.dma.start_h2d_ini(len, bytes)?;
+        if self.mode == CompletionMode::Interrupt {
+            self.enable_uio_irqs()?;
+        }
+        self.dma.start_h2d_fini(phys_addr, bytes);
         Ok(())
     }
 
-    pub fn start_d2h(&mut self, buff: &DmaBuffer, bytes: usize) -> Result<(), Error> {
-        self.dma.start_d2h_ini(buff, bytes);
-        self.enable_uio_irqs()?;
-        self.dma.start_d2h_fini(buff, bytes);
+    /// Start a transfer. The buffer stays borrowed until the returned
+    /// [`Transfer`] is completed.
+    ///
+    /// Fails with [`Error::OutOfBounds`] if `bytes` exceeds the buffer.
+    pub fn start_d2h<'a, 'b, M: DmaMemory + ?Sized>(
+        &'a mut self,
+        buff: &'b mut M,
+        bytes: usize,
+    ) -> Result<Transfer<'a, &'b mut M>, Error> {
+        self.start_d2h_at(buff.phys_addr(), buff.len(), bytes)?;
+        Ok(Transfer::new(self, buff, Channel::D2h))
+    }
+
+    fn start_d2h_at(&mut self, phys_addr: usize, len: usize, bytes: usize) -> Result<(), Error> {
+        self.dma.start_d2h_ini(len, bytes)?;
+        if self.mode == CompletionMode::Interrupt {
+            self.enable_uio_irqs()?;
+        }
+        self.dma.start_d2h_fini(phys_addr, bytes);
         Ok(())
     }
 
+    pub(crate) fn check_errors_h2d(&self) -> Result<(), Error> {
+        self.dma.check_errors(self.dma.status(MM2S_DMASR))
+    }
+
+    pub(crate) fn check_errors_d2h(&self) -> Result<(), Error> {
+        self.dma.check_errors(self.dma.status(S2MM_DMASR))
+    }
+
     fn enable_uio_irqs(&mut self) -> Result<(), Error> {
         self.dev_fd.write_all(&[1u8, 0, 0, 0])?;
         Ok(())
     }
 
+    fn disable_uio_irqs(&mut self) -> Result<(), Error> {
+        self.dev_fd.write_all(&[0u8, 0, 0, 0])?;
+        Ok(())
+    }
+
+    fn wait_irq(&mut self) -> Result<(), Error> {
+        let mut buf = [0u8; 4];
+        self.dev_fd.read_exact(&mut buf)?;
+        Ok(())
+    }
+
+    /// Drop the interrupt of an aborted transfer, so that it is not taken for
+    /// the completion of the next one.
+    fn discard_irq(&mut self, channel: Channel) -> Result<(), Error> {
+        // Clear the flags first, the interrupt would fire again otherwise.
+        self.dma.clear_irqs(channel.dmasr());
+        if uio_irq_pending(&self.dev_fd)? {
+            self.wait_irq()?;
+        }
+        if self.mode == CompletionMode::Interrupt {
+            self.enable_uio_irqs()?;
+        }
+        Ok(())
+    }
+
     #[cfg(feature = "scatter-gather")]
     pub fn enqueue_sg_h2d(&mut self, descriptor: &mut SgDescriptor) -> Result<(), Error> {
         self.dma.enqueue_sg_h2d(descriptor)
@@ -105,8 +475,139 @@ impl AxiDma {
         self.dma.enqueue_sg_d2h(descriptor)
     }
 
+    /// Enqueue the descriptor at `index` of `ring`. Descriptors have to be
+    /// enqueued in ring order for [`AxiDma::completed_h2d`] to work.
+    ///
+    /// Fails with [`Error::DescriptorBusy`] if the descriptor was submitted
+    /// before and has not been reaped through the completions yet.
+    #[cfg(feature = "scatter-gather")]
+    pub fn enqueue_sg_ring_h2d(&mut self, ring: &mut SgRing, index: usize) -> Result<(), Error> {
+        sg_check_free(ring, index)?;
+        self.dma.enqueue_sg_h2d(ring.descriptor_mut(index))?;
+        ring.mark_submitted(index);
+        Ok(())
+    }
+
+    /// Enqueue the descriptor at `index` of `ring`. Descriptors have to be
+    /// enqueued in ring order for [`AxiDma::completed_d2h`] to work.
+    ///
+    /// Fails with [`Error::DescriptorBusy`] if the descriptor was submitted
+    /// before and has not been reaped through the completions yet.
+    #[cfg(feature = "scatter-gather")]
+    pub fn enqueue_sg_ring_d2h(&mut self, ring: &mut SgRing, index: usize) -> Result<(), Error> {
+        sg_check_free(ring, index)?;
+        self.dma.enqueue_sg_d2h(ring.descriptor_mut(index))?;
+        ring.mark_submitted(index);
+        Ok(())
+    }
+
+    /// Check whether `descriptor` has been completed without blocking.
+    ///
+    /// A pending interrupt is consumed and the interrupt is re-armed, so that
+    /// the UIO device becomes readable on the next completion. This allows to
+    /// wait for completions in an event loop.
+    #[cfg(feature = "scatter-gather")]
+    pub fn try_complete_h2d(&mut self, descriptor: &SgDescriptor) -> Result<bool, Error> {
+        self.service_sg_irqs(MM2S_DMASR)?;
+        Ok(sg_completed(descriptor))
+    }
+
+    /// Check whether `descriptor` has been completed without blocking.
+    ///
+    /// See [`AxiDma::try_complete_h2d`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn try_complete_d2h(&mut self, descriptor: &SgDescriptor) -> Result<bool, Error> {
+        self.service_sg_irqs(S2MM_DMASR)?;
+        Ok(sg_completed(descriptor))
+    }
+
+    /// Iterate over all descriptors of `ring` that completed since the last
+    /// call, in ring order. Interrupts are handled like in
+    /// [`AxiDma::try_complete_h2d`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn completed_h2d<'a>(
+        &'a mut self,
+        ring: &'a mut SgRing,
+    ) -> Result<SgCompletions<'a>, Error> {
+        self.service_sg_irqs(MM2S_DMASR)?;
+        Ok(SgCompletions::new(&self.dma, MM2S_DMASR, ring))
+    }
+
+    /// Iterate over all descriptors of `ring` that completed since the last
+    /// call, in ring order. Interrupts are handled like in
+    /// [`AxiDma::try_complete_d2h`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn completed_d2h<'a>(
+        &'a mut self,
+        ring: &'a mut SgRing,
+    ) -> Result<SgCompletions<'a>, Error> {
+        self.service_sg_irqs(S2MM_DMASR)?;
+        Ok(SgCompletions::new(&self.dma, S2MM_DMASR, ring))
+    }
+
+    /// Recover the H2D channel from an error reported in DMASR.
+    ///
+    /// The faulting descriptor is identified through CURRDESC and the core is
+    /// reset. The statuses of the descriptors that the DMA did not get to are
+    /// cleared, and the channel is restarted with these descriptors up to
+    /// TAILDESC. Once the channel is running again, [`Error::SgFault`] is
+    /// returned with the address and STATUS word of the faulting descriptor.
+    /// If there is no error, `Ok(())` is returned.
+    ///
+    /// The faulting descriptor is not completed. [`AxiDma::completed_h2d`]
+    /// yields the same [`Error::SgFault`] in its place, which also reaps it.
+    ///
+    /// Resetting the core halts the D2H channel as well. If it was working
+    /// through its descriptors, it is restarted from its current descriptor,
+    /// which is transferred again from the start.
+    #[cfg(feature = "scatter-gather")]
+    pub fn recover_sg_h2d(&mut self, ring: &mut SgRing) -> Result<(), Error> {
+        self.dma.recover_sg(&MM2S_SG, ring)
+    }
+
+    /// Recover the D2H channel from an error reported in DMASR.
+    ///
+    /// See [`AxiDma::recover_sg_h2d`]. An H2D channel that was working
+    /// through its descriptors is restarted from its current descriptor.
+    #[cfg(feature = "scatter-gather")]
+    pub fn recover_sg_d2h(&mut self, ring: &mut SgRing) -> Result<(), Error> {
+        self.dma.recover_sg(&S2MM_SG, ring)
+    }
+
+    #[cfg(feature = "scatter-gather")]
+    fn service_sg_irqs(&mut self, dmasr: isize) -> Result<(), Error> {
+        if self.mode == CompletionMode::Interrupt {
+            if uio_irq_pending(&self.dev_fd)? {
+                self.wait_irq()?;
+            }
+            // Clear the flags before re-arming, so that only new completions
+            // raise an interrupt.
+            self.dma.ack_sg_irqs(dmasr)?;
+            self.enable_uio_irqs()?;
+        } else {
+            self.dma.check_errors(self.dma.status(dmasr))?;
+        }
+        Ok(())
+    }
+
     #[cfg(feature = "scatter-gather")]
     pub fn wait_sg_complete_h2d(&mut self, descriptor: &SgDescriptor) -> Result<(), Error> {
+        if let CompletionMode::Poll {
+            spin_budget,
+            irq_fallback,
+        } = self.mode
+        {
+            if self
+                .dma
+                .poll_sg_complete(MM2S_DMASR, descriptor, spin_budget)?
+            {
+                return Ok(());
+            }
+            if !irq_fallback {
+                return Err(Error::Timeout);
+            }
+        }
+
         loop {
             if descriptor.completed() {
                 dmb(); // the complete flag acts as an acquire lock
@@ -116,15 +617,31 @@ impl AxiDma {
             // Wait for an interrupt that might indicate that the descriptor has
             // been completed.
             self.enable_uio_irqs()?;
-            self.wait_h2d()?;
+            self.wait_irq()?;
 
-            self.dma.wait_sg_complete_h2d_fini()?;
+            self.dma.ack_sg_irqs(MM2S_DMASR)?;
         }
         Ok(())
     }
 
     #[cfg(feature = "scatter-gather")]
     pub fn wait_sg_complete_d2h(&mut self, descriptor: &SgDescriptor) -> Result<(), Error> {
+        if let CompletionMode::Poll {
+            spin_budget,
+            irq_fallback,
+        } = self.mode
+        {
+            if self
+                .dma
+                .poll_sg_complete(S2MM_DMASR, descriptor, spin_budget)?
+            {
+                return Ok(());
+            }
+            if !irq_fallback {
+                return Err(Error::Timeout);
+            }
+        }
+
         loop {
             if descriptor.completed() {
                 dmb(); // the complete flag acts as an acquire lock
@@ -134,13 +651,15 @@ impl AxiDma {
             // Wait for an interrupt that might indicate that the descriptor has
             // been completed.
             self.enable_uio_irqs()?;
-            self.wait_d2h()?;
+            self.wait_irq()?;
 
-            self.dma.wait_sg_complete_d2h_fini()?;
+            self.dma.ack_sg_irqs(S2MM_DMASR)?;
         }
         Ok(())
     }
 
+    /// Reset the core. This halts both channels, aborting the transfers in
+    /// either direction.
     pub fn reset(&mut self) {
         self.dma.reset();
     }
@@ -154,25 +673,145 @@ impl AxiDma {
     }
 
     pub fn wait_d2h(&mut self) -> Result<(), Error> {
-        let mut buf = [0u8; 4];
-        self.dev_fd.read_exact(&mut buf)?;
-        Ok(())
+        if let CompletionMode::Poll {
+            spin_budget,
+            irq_fallback,
+        } = self.mode
+        {
+            if self.dma.poll_complete(S2MM_DMASR, spin_budget)? {
+                return Ok(());
+            }
+            if !irq_fallback {
+                return Err(Error::Timeout);
+            }
+            // The interrupt is level-triggered, so it fires right away if the
+            // transfer completed in the meantime.
+            self.enable_uio_irqs()?;
+        }
+        self.wait_irq()
     }
 
     pub fn wait_h2d(&mut self) -> Result<(), Error> {
-        let mut buf = [0u8; 4];
-        self.dev_fd.read_exact(&mut buf)?;
-        Ok(())
+        if let CompletionMode::Poll {
+            spin_budget,
+            irq_fallback,
+        } = self.mode
+        {
+            if self.dma.poll_complete(MM2S_DMASR, spin_budget)? {
+                return Ok(());
+            }
+            if !irq_fallback {
+                return Err(Error::Timeout);
+            }
+            // The interrupt is level-triggered, so it fires right away if the
+            // transfer completed in the meantime.
+            self.enable_uio_irqs()?;
+        }
+        self.wait_irq()
+    }
+
+    /// Wait up to `timeout` for a transfer to complete. Returns `false` if it
+    /// did not complete in time.
+    ///
+    /// In polling mode without interrupt fallback, the spin budget limits the
+    /// wait instead.
+    pub fn wait_d2h_timeout(&mut self, timeout: Duration) -> Result<bool, Error> {
+        self.wait_timeout(S2MM_DMASR, timeout)
+    }
+
+    /// Wait up to `timeout` for a transfer to complete. Returns `false` if it
+    /// did not complete in time.
+    ///
+    /// In polling mode without interrupt fallback, the spin budget limits the
+    /// wait instead.
+    pub fn wait_h2d_timeout(&mut self, timeout: Duration) -> Result<bool, Error> {
+        self.wait_timeout(MM2S_DMASR, timeout)
+    }
+
+    fn wait_timeout(&mut self, dmasr: isize, timeout: Duration) -> Result<bool, Error> {
+        if let CompletionMode::Poll {
+            spin_budget,
+            irq_fallback,
+        } = self.mode
+        {
+            if self.dma.poll_complete(dmasr, spin_budget)? {
+                return Ok(true);
+            }
+            if !irq_fallback {
+                return Ok(false);
+            }
+            self.enable_uio_irqs()?;
+        }
+        if uio_irq_wait(&self.dev_fd, timeout)? {
+            self.wait_irq()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
     }
 
     pub fn size_d2h(&self) -> usize {
         self.dma.size_d2h()
     }
+
+    /// Physical address of the descriptor the H2D channel is working on.
+    /// Use [`SgRing::index_of`] to map it to a descriptor of a ring.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_descriptor_h2d(&self) -> u64 {
+        self.dma
+            .descriptor_register(MM2S_CURRDESC, MM2S_CURRDESC_MSB)
+    }
+
+    /// Physical address of the last descriptor enqueued on the H2D channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_descriptor_h2d(&self) -> u64 {
+        self.dma
+            .descriptor_register(MM2S_TAILDESC, MM2S_TAILDESC_MSB)
+    }
+
+    /// Physical address of the descriptor the D2H channel is working on.
+    /// Use [`SgRing::index_of`] to map it to a descriptor of a ring.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_descriptor_d2h(&self) -> u64 {
+        self.dma
+            .descriptor_register(S2MM_CURRDESC, S2MM_CURRDESC_MSB)
+    }
+
+    /// Physical address of the last descriptor enqueued on the D2H channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_descriptor_d2h(&self) -> u64 {
+        self.dma
+            .descriptor_register(S2MM_TAILDESC, S2MM_TAILDESC_MSB)
+    }
+
+    /// Index of the descriptor of `ring` the H2D channel is working on.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_index_h2d(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.current_descriptor_h2d())
+    }
+
+    /// Index of the last descriptor of `ring` enqueued on the H2D channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_index_h2d(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.tail_descriptor_h2d())
+    }
+
+    /// Index of the descriptor of `ring` the D2H channel is working on.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_index_d2h(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.current_descriptor_d2h())
+    }
+
+    /// Index of the last descriptor of `ring` enqueued on the D2H channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_index_d2h(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.tail_descriptor_d2h())
+    }
 }
 
 impl AxiDmaBase {
-    fn new(uio: &str, dev_fd: RawFd) -> Result<AxiDmaBase, Error> {
-        let mut size_f = File::open(format!("/sys/class/uio/{}/maps/map0/size", uio))?;
+    fn new(uio: &str, root: &SysRoot, dev_fd: RawFd) -> Result<AxiDmaBase, Error> {
+        let mut size_f = File::open(root.uio(uio).join("maps/map0/size"))?;
         let mut buf = String::new();
         size_f.read_to_string(&mut buf)?;
         let buf = buf.trim().trim_start_matches("0x");
@@ -200,8 +839,8 @@ impl AxiDmaBase {
         })
     }
 
-    fn start_h2d_ini(&mut self, buff: &DmaBuffer, bytes: usize) {
-        debug_assert!(buff.size() >= bytes);
+    fn start_h2d_ini(&mut self, len: usize, bytes: usize) -> Result<(), Error> {
+        check_transfer_len(len, bytes)?;
         unsafe {
             // Ensure that the DDR buffer has been written to
             dmb();
@@ -209,45 +848,41 @@ impl AxiDmaBase {
             // clear irqs in dma
             ptr::write_volatile(self.base.offset(MM2S_DMASR), 0x7000);
         }
+        Ok(())
     }
 
-    fn start_h2d_fini(&mut self, buff: &DmaBuffer, bytes: usize) {
+    fn start_h2d_fini(&mut self, phys_addr: usize, bytes: usize) {
         unsafe {
             // Configure AXIDMA - MM2S (PS -> PL)
             ptr::write_volatile(self.base.offset(MM2S_DMACR), 0x7001);
             #[allow(clippy::identity_op)]
-            ptr::write_volatile(
-                self.base.offset(MM2S_SA),
-                (buff.phys_addr() & 0xffff_ffff) as u32,
-            );
+            ptr::write_volatile(self.base.offset(MM2S_SA), (phys_addr & 0xffff_ffff) as u32);
             ptr::write_volatile(
                 self.base.offset(MM2S_SA_MSB),
-                (buff.phys_addr() & !0xffff_ffff).wrapping_shr(32) as u32,
+                (phys_addr & !0xffff_ffff).wrapping_shr(32) as u32,
             );
             ptr::write_volatile(self.base.offset(MM2S_LENGTH), bytes as u32);
         }
     }
 
-    fn start_d2h_ini(&mut self, buff: &DmaBuffer, bytes: usize) {
-        debug_assert!(buff.size() >= bytes);
+    fn start_d2h_ini(&mut self, len: usize, bytes: usize) -> Result<(), Error> {
+        check_transfer_len(len, bytes)?;
         unsafe {
             // clear irqs in dma
             ptr::write_volatile(self.base.offset(S2MM_DMASR), 0x7000);
         }
+        Ok(())
     }
 
-    fn start_d2h_fini(&mut self, buff: &DmaBuffer, bytes: usize) {
+    fn start_d2h_fini(&mut self, phys_addr: usize, bytes: usize) {
         unsafe {
             // Configure AXIDMA - S2MM (PL -> PS)
             ptr::write_volatile(self.base.offset(S2MM_DMACR), 0x7001);
             #[allow(clippy::identity_op)]
-            ptr::write_volatile(
-                self.base.offset(S2MM_DA),
-                (buff.phys_addr() & 0xffff_ffff) as u32,
-            );
+            ptr::write_volatile(self.base.offset(S2MM_DA), (phys_addr & 0xffff_ffff) as u32);
             ptr::write_volatile(
                 self.base.offset(S2MM_DA_MSB),
-                (buff.phys_addr() & !0xffff_ffff).wrapping_shr(32) as u32,
+                (phys_addr & !0xffff_ffff).wrapping_shr(32) as u32,
             );
             ptr::write_volatile(self.base.offset(S2MM_LENGTH), bytes as u32);
         }
@@ -381,26 +1016,108 @@ impl AxiDmaBase {
         Ok(())
     }
 
+    /// Acknowledge the interrupt flags of a channel after checking them for
+    /// errors.
     #[cfg(feature = "scatter-gather")]
-    fn wait_sg_complete_h2d_fini(&mut self) -> Result<(), Error> {
+    fn ack_sg_irqs(&mut self, dmasr: isize) -> Result<(), Error> {
         unsafe {
             // check that there are no errors
-            self.check_errors(ptr::read_volatile(self.base.offset(MM2S_DMASR)))?;
+            self.check_errors(ptr::read_volatile(self.base.offset(dmasr)))?;
             // clear irqs in dma
-            ptr::write_volatile(self.base.offset(MM2S_DMASR), 0x7000);
+            ptr::write_volatile(self.base.offset(dmasr), 0x7000);
         }
         Ok(())
     }
 
     #[cfg(feature = "scatter-gather")]
-    fn wait_sg_complete_d2h_fini(&mut self) -> Result<(), Error> {
+    fn recover_sg(&mut self, regs: &SgRegisters, ring: &mut SgRing) -> Result<(), Error> {
+        let dmasr = self.status(regs.dmasr);
+        let err = match self.check_errors(dmasr) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let current = self.descriptor_register(regs.currdesc, regs.currdesc_msb);
+        let tail = self.descriptor_register(regs.taildesc, regs.taildesc_msb);
+        let (fault, last) = match (ring_index(ring, current), ring_index(ring, tail)) {
+            (Some(fault), Some(last)) => (fault, last),
+            _ => return Err(err),
+        };
+        let status = ring.descriptor(fault).status();
+
+        // The reset halts the other channel, too. If it was in the middle of
+        // its descriptors, it is restarted where it stopped.
+        let other = regs.other();
+        let other_busy = self.busy(other.dmasr);
+        let other_current = self.descriptor_register(other.currdesc, other.currdesc_msb);
+        let other_tail = self.descriptor_register(other.taildesc, other.taildesc_msb);
+
+        self.reset();
+
+        let mut index = fault;
+        while index != last {
+            index = (index + 1) % ring.len();
+            ring.descriptor_mut(index).clear_status();
+        }
+        let phys_addr = ring.descriptor(fault).phys_addr();
+        ring.set_fault(
+            fault,
+            Error::SgFault {
+                phys_addr,
+                status,
+                dmasr,
+            },
+        );
+
+        if fault != last {
+            let next = ring.descriptor((fault + 1) % ring.len()).phys_addr() as u64;
+            self.restart_sg(regs, next, tail);
+        }
+        if other_busy {
+            self.restart_sg(other, other_current, other_tail);
+        }
+
+        Err(Error::SgFault {
+            phys_addr,
+            status,
+            dmasr,
+        })
+    }
+
+    /// Start a halted channel at descriptor `current`, running up to `tail`.
+    #[cfg(feature = "scatter-gather")]
+    fn restart_sg(&mut self, regs: &SgRegisters, current: u64, tail: u64) {
+        // Ensure that the descriptors have been written to
+        dmb();
         unsafe {
-            // check that there are no errors
-            self.check_errors(ptr::read_volatile(self.base.offset(S2MM_DMASR)))?;
-            // clear irqs in dma
-            ptr::write_volatile(self.base.offset(S2MM_DMASR), 0x7000);
+            // The DMA is halted, so CURRDESC can be written.
+            #[allow(clippy::identity_op)]
+            ptr::write_volatile(
+                self.base.offset(regs.currdesc),
+                (current & 0xffff_ffff) as u32,
+            );
+            ptr::write_volatile(
+                self.base.offset(regs.currdesc_msb),
+                (current & !0xffff_ffff).wrapping_shr(32) as u32,
+            );
+            ptr::write_volatile(self.base.offset(regs.dmacr), 0x0001_5001);
+            // Writing the LSB of TAILDESC starts the DMA.
+            ptr::write_volatile(
+                self.base.offset(regs.taildesc_msb),
+                (tail & !0xffff_ffff).wrapping_shr(32) as u32,
+            );
+            #[allow(clippy::identity_op)]
+            ptr::write_volatile(self.base.offset(regs.taildesc), (tail & 0xffff_ffff) as u32);
+        }
+    }
+
+    /// Read a descriptor address register, combining the LSB and MSB halves.
+    #[cfg(feature = "scatter-gather")]
+    fn descriptor_register(&self, lsb: isize, msb: isize) -> u64 {
+        unsafe {
+            let lsbs = ptr::read_volatile(self.base.offset(lsb)) as u64;
+            let msbs = ptr::read_volatile(self.base.offset(msb)) as u64;
+            (msbs << 32) | lsbs
         }
-        Ok(())
     }
 
     fn reset(&mut self) {
@@ -497,6 +1214,12 @@ impl AxiDmaBase {
             print!("err_irq, ");
         }
         println!();
+        #[cfg(feature = "scatter-gather")]
+        println!(
+            "h2d descriptors: current {:#x}, tail {:#x}",
+            self.descriptor_register(MM2S_CURRDESC, MM2S_CURRDESC_MSB),
+            self.descriptor_register(MM2S_TAILDESC, MM2S_TAILDESC_MSB)
+        );
     }
 
     fn status_d2h(&self) {
@@ -570,13 +1293,69 @@ impl AxiDmaBase {
             print!("err_irq, ");
         }
         println!();
+        #[cfg(feature = "scatter-gather")]
+        println!(
+            "d2h descriptors: current {:#x}, tail {:#x}",
+            self.descriptor_register(S2MM_CURRDESC, S2MM_CURRDESC_MSB),
+            self.descriptor_register(S2MM_TAILDESC, S2MM_TAILDESC_MSB)
+        );
+    }
+
+    fn status(&self, dmasr: isize) -> u32 {
+        unsafe { ptr::read_volatile(self.base.offset(dmasr)) }
     }
 
     fn size_d2h(&self) -> usize {
         unsafe { ptr::read_volatile(self.base.offset(S2MM_LENGTH)) as usize }
     }
 
+    fn clear_irqs(&mut self, dmasr: isize) {
+        unsafe {
+            ptr::write_volatile(self.base.offset(dmasr), 0x7000);
+        }
+    }
+
+    /// Whether a register mode transfer is still running, i.e., the channel
+    /// is neither halted nor idle.
+    fn busy(&self, dmasr: isize) -> bool {
+        self.status(dmasr) & 0x3 == 0
+    }
+
+    /// Spin on a register mode transfer until DMASR reports Idle or IOC_Irq.
+    /// Returns `false` if the spin budget is exhausted first.
+    fn poll_complete(&self, dmasr: isize, spin_budget: u64) -> Result<bool, Error> {
+        for _ in 0..spin_budget {
+            let status = self.status(dmasr);
+            self.check_errors(status)?;
+            if status & (1 << 1 | 1 << 12) != 0 {
+                dmb(); // the status register acts as an acquire lock
+                return Ok(true);
+            }
+            std::hint::spin_loop();
+        }
+        Ok(false)
+    }
+
+    /// Spin on the completed flag of a descriptor, checking DMASR for errors
+    /// in between. Returns `false` if the spin budget is exhausted first.
     #[cfg(feature = "scatter-gather")]
+    fn poll_sg_complete(
+        &self,
+        dmasr: isize,
+        descriptor: &SgDescriptor,
+        spin_budget: u64,
+    ) -> Result<bool, Error> {
+        for _ in 0..spin_budget {
+            if descriptor.completed() {
+                dmb(); // the complete flag acts as an acquire lock
+                return Ok(true);
+            }
+            self.check_errors(self.status(dmasr))?;
+            std::hint::spin_loop();
+        }
+        Ok(false)
+    }
+
     fn check_errors(&self, status: u32) -> Result<(), Error> {
         let dma_int_err = status & (1 << 4) != 0;
         let dma_slv_err = status & (1 << 5) != 0;
@@ -606,6 +1385,138 @@ impl AxiDmaBase {
     }
 }
 
+/// Iterator over the descriptors of an [`SgRing`] that completed since the
+/// last call, in ring order.
+///
+/// Iteration stops at the first descriptor that is still owned by the DMA. If
+/// the DMA reports an error in DMASR, it is yielded once and the iteration
+/// ends. A descriptor that faulted and was skipped by a recovery (see
+/// [`AxiDma::recover_sg_h2d`]) is yielded as [`Error::SgFault`] in its place.
+#[cfg(feature = "scatter-gather")]
+pub struct SgCompletions<'a> {
+    dma: &'a AxiDmaBase,
+    dmasr: isize,
+    descriptors: &'a [SgDescriptor],
+    submitted: &'a mut [bool],
+    next: &'a mut usize,
+    fault: &'a mut Option<(usize, Error)>,
+    done: bool,
+}
+
+#[cfg(feature = "scatter-gather")]
+impl<'a> SgCompletions<'a> {
+    fn new(dma: &'a AxiDmaBase, dmasr: isize, ring: &'a mut SgRing) -> SgCompletions<'a> {
+        let (descriptors, submitted, next, fault) = ring.parts_mut();
+        SgCompletions {
+            dma,
+            dmasr,
+            descriptors,
+            submitted,
+            next,
+            fault,
+            done: false,
+        }
+    }
+}
+
+#[cfg(feature = "scatter-gather")]
+impl<'a> Iterator for SgCompletions<'a> {
+    type Item = Result<(usize, &'a SgDescriptor), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = *self.next;
+        if self.done || !self.submitted[index] {
+            return None;
+        }
+        if matches!(self.fault, Some((i, _)) if *i == index) {
+            let (_, e) = self.fault.take().unwrap();
+            self.submitted[index] = false;
+            *self.next = (index + 1) % self.descriptors.len();
+            return Some(Err(e));
+        }
+        let descriptor = &self.descriptors[index];
+        if !sg_completed(descriptor) {
+            self.done = true;
+            return match self.dma.check_errors(self.dma.status(self.dmasr)) {
+                Ok(()) => None,
+                Err(e) => Some(Err(e)),
+            };
+        }
+        self.submitted[index] = false;
+        *self.next = (index + 1) % self.descriptors.len();
+        Some(Ok((index, descriptor)))
+    }
+}
+
+/// Make sure a register mode transfer of `bytes` fits the buffer and the
+/// LENGTH register, before anything is programmed.
+fn check_transfer_len(len: usize, bytes: usize) -> Result<(), Error> {
+    if bytes > len {
+        return Err(Error::OutOfBounds {
+            offset: 0,
+            len: bytes,
+            size: len,
+        });
+    }
+    if u32::try_from(bytes).is_err() {
+        return Err(Error::TooLong {
+            len: bytes,
+            max: u32::MAX as usize,
+        });
+    }
+    Ok(())
+}
+
+/// Map a descriptor address register to the index of a descriptor of `ring`.
+#[cfg(feature = "scatter-gather")]
+fn ring_index(ring: &SgRing, phys_addr: u64) -> Option<usize> {
+    usize::try_from(phys_addr)
+        .ok()
+        .and_then(|addr| ring.index_of(addr))
+}
+
+#[cfg(feature = "scatter-gather")]
+fn sg_completed(descriptor: &SgDescriptor) -> bool {
+    if descriptor.completed() {
+        dmb(); // the complete flag acts as an acquire lock
+        true
+    } else {
+        false
+    }
+}
+
+/// Make sure the descriptor at `index` is free, i.e., it was either never
+/// submitted or its completion has been reaped.
+#[cfg(feature = "scatter-gather")]
+fn sg_check_free(ring: &SgRing, index: usize) -> Result<(), Error> {
+    if ring.is_submitted(index) {
+        return Err(Error::DescriptorBusy(index));
+    }
+    Ok(())
+}
+
+/// Check, without blocking, whether the UIO device has an interrupt event
+/// that was not read yet.
+fn uio_irq_pending(dev_fd: &File) -> Result<bool, Error> {
+    uio_irq_wait(dev_fd, Duration::ZERO)
+}
+
+/// Wait up to `timeout` for the UIO device to have an interrupt event that
+/// was not read yet.
+fn uio_irq_wait(dev_fd: &File, timeout: Duration) -> Result<bool, Error> {
+    let mut pfd = libc::pollfd {
+        fd: dev_fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    let ret = unsafe { libc::poll(&mut pfd, 1, timeout) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(ret > 0 && pfd.revents & libc::POLLIN != 0)
+}
+
 impl Drop for AxiDmaBase {
     fn drop(&mut self) {
         unsafe {
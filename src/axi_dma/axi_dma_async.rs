@@ -1,23 +1,95 @@
+#[cfg(feature = "async")]
 use async_io::Async;
 use std::fmt;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::future::poll_fn;
+use std::io;
 use std::io::prelude::*;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
+use std::task::{ready, Context, Poll};
+#[cfg(feature = "tokio")]
+use tokio::io::unix::AsyncFd;
 
+use super::uio_irq_pending;
 use super::AxiDmaBase;
+use super::AxiDmaOptions;
+use super::Channel;
+#[cfg(feature = "scatter-gather")]
+use super::{
+    ring_index, sg_check_free, sg_completed, SgCompletions, MM2S_CURRDESC, MM2S_CURRDESC_MSB,
+    MM2S_SG, MM2S_TAILDESC, MM2S_TAILDESC_MSB, S2MM_CURRDESC, S2MM_CURRDESC_MSB, S2MM_SG,
+    S2MM_TAILDESC, S2MM_TAILDESC_MSB,
+};
+use super::{MM2S_DMASR, S2MM_DMASR};
 #[cfg(feature = "scatter-gather")]
 use crate::dmb;
-use crate::DmaBuffer;
+use crate::DmaMemory;
 use crate::Error;
 #[cfg(feature = "scatter-gather")]
 use crate::SgDescriptor;
+#[cfg(feature = "scatter-gather")]
+use crate::SgRing;
+use crate::SysRoot;
+use crate::UioDevice;
 
+/// Async AXI DMA driver.
+///
+/// The UIO device is registered either with the `async-io` reactor (feature
+/// `async`) or with the reactor of the current tokio runtime (feature
+/// `tokio`). All register and descriptor handling is shared, so both behave
+/// identically.
 pub struct AxiDmaAsync {
-    dev_fd: Async<File>,
+    dev_fd: AsyncUio,
     dma: AxiDmaBase,
 }
 
+/// The UIO device file, registered with the reactor of an async runtime.
+#[derive(Debug)]
+enum AsyncUio {
+    #[cfg(feature = "async")]
+    AsyncIo(Async<File>),
+    #[cfg(feature = "tokio")]
+    Tokio(AsyncFd<File>),
+}
+
+impl AsyncUio {
+    fn file(&self) -> &File {
+        match self {
+            #[cfg(feature = "async")]
+            AsyncUio::AsyncIo(f) => f.get_ref(),
+            #[cfg(feature = "tokio")]
+            AsyncUio::Tokio(f) => f.get_ref(),
+        }
+    }
+
+    /// Wait until an interrupt event can be read, without consuming it.
+    fn poll_irq_pending(&self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        // Events might be consumed without the reactor knowing, so its
+        // readiness is double-checked.
+        loop {
+            match self {
+                #[cfg(feature = "async")]
+                AsyncUio::AsyncIo(f) => {
+                    ready!(f.poll_readable(cx))?;
+                    if uio_irq_pending(f.get_ref())? {
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                #[cfg(feature = "tokio")]
+                AsyncUio::Tokio(f) => {
+                    let mut guard = ready!(f.poll_read_ready(cx))?;
+                    if uio_irq_pending(f.get_ref())? {
+                        return Poll::Ready(Ok(()));
+                    }
+                    guard.clear_ready();
+                }
+            }
+        }
+    }
+}
+
 impl fmt::Debug for AxiDmaAsync {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "AxiDmaAsync ({})", &self.dma.dev)?;
@@ -27,42 +99,258 @@ impl fmt::Debug for AxiDmaAsync {
     }
 }
 
+/// A register mode transfer in flight, see [`crate::Transfer`].
+///
+/// Dropping an unfinished transfer, e.g., by dropping the future returned by
+/// [`AsyncTransfer::wait`], resets the DMA if the transfer is still running
+/// and discards its interrupt. The reset halts both channels, so a transfer
+/// in the other direction is aborted as well.
+#[must_use = "dropping a transfer aborts it"]
+#[derive(Debug)]
+pub struct AsyncTransfer<'a, B> {
+    dma: &'a mut AxiDmaAsync,
+    _buffer: B,
+    channel: Channel,
+    done: bool,
+}
+
+impl<'a, B> AsyncTransfer<'a, B> {
+    fn new(dma: &'a mut AxiDmaAsync, buffer: B, channel: Channel) -> AsyncTransfer<'a, B> {
+        AsyncTransfer {
+            dma,
+            _buffer: buffer,
+            channel,
+            done: false,
+        }
+    }
+
+    /// Wait for the transfer to complete.
+    pub async fn wait(mut self) -> Result<(), Error> {
+        poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    /// Poll for the completion of the transfer.
+    pub fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let res = match self.channel {
+            Channel::H2d => ready!(self.dma.poll_wait_h2d(cx)),
+            Channel::D2h => ready!(self.dma.poll_wait_d2h(cx)),
+        };
+        self.done = true;
+        Poll::Ready(res)
+    }
+}
+
+impl<B> Drop for AsyncTransfer<'_, B> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.dma.abort(self.channel);
+        }
+    }
+}
+
 impl AxiDmaAsync {
+    /// Open the DMA with the `async-io` reactor.
+    #[cfg(feature = "async")]
     pub fn new(uio: &str) -> Result<AxiDmaAsync, Error> {
-        let dev_fd = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(format!("/dev/{}", uio))?;
-        let dma = AxiDmaBase::new(uio, dev_fd.as_raw_fd())?;
+        Self::with_root(uio, &SysRoot::default())
+    }
+
+    /// Open the DMA with sysfs and device nodes located under `root`.
+    #[cfg(feature = "async")]
+    pub fn with_root(uio: &str, root: &SysRoot) -> Result<AxiDmaAsync, Error> {
+        AxiDmaOptions::new().root(root.clone()).open_async(uio)
+    }
+
+    /// DMA of a [`FakeDma`](super::fake::FakeDma), with a socket as UIO
+    /// device.
+    #[cfg(all(test, feature = "async"))]
+    pub(super) fn from_fake(dma: AxiDmaBase, irq: File) -> AxiDmaAsync {
+        AxiDmaAsync {
+            dev_fd: AsyncUio::AsyncIo(Async::new(irq).unwrap()),
+            dma,
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn open_async(uio: &str, options: &AxiDmaOptions) -> Result<AxiDmaAsync, Error> {
+        let (dev_fd, dma) = Self::open(uio, options)?;
         Ok(AxiDmaAsync {
-            dev_fd: Async::new(dev_fd)?,
+            dev_fd: AsyncUio::AsyncIo(Async::new(dev_fd)?),
             dma,
         })
     }
 
-    pub async fn start_h2d(&mut self, buff: &DmaBuffer, bytes: usize) -> Result<(), Error> {
-        self.dma.start_h2d_ini(buff, bytes);
-        self.enable_uio_irqs().await?;
-        self.dma.start_h2d_fini(buff, bytes);
+    /// Open the DMA with the reactor of the current tokio runtime.
+    ///
+    /// # Panics
+    /// If called outside of a tokio runtime with I/O enabled.
+    #[cfg(feature = "tokio")]
+    pub fn new_tokio(uio: &str) -> Result<AxiDmaAsync, Error> {
+        Self::new_tokio_with_root(uio, &SysRoot::default())
+    }
+
+    /// Open the DMA with the reactor of the current tokio runtime and sysfs
+    /// and device nodes located under `root`.
+    ///
+    /// # Panics
+    /// If called outside of a tokio runtime with I/O enabled.
+    #[cfg(feature = "tokio")]
+    pub fn new_tokio_with_root(uio: &str, root: &SysRoot) -> Result<AxiDmaAsync, Error> {
+        AxiDmaOptions::new().root(root.clone()).open_tokio(uio)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn open_tokio(uio: &str, options: &AxiDmaOptions) -> Result<AxiDmaAsync, Error> {
+        let (dev_fd, dma) = Self::open(uio, options)?;
+        // SAFETY: the file owns its descriptor, which stays open until the
+        // AsyncFd is dropped.
+        let dev_fd = unsafe { AsyncFd::register(dev_fd) }.map_err(io::Error::from)?;
+        Ok(AxiDmaAsync {
+            dev_fd: AsyncUio::Tokio(dev_fd),
+            dma,
+        })
+    }
+
+    /// Open the DMA with the reactor of the current tokio runtime.
+    ///
+    /// # Panics
+    /// If called outside of a tokio runtime with I/O enabled.
+    #[cfg(all(feature = "tokio", not(feature = "async")))]
+    pub fn new(uio: &str) -> Result<AxiDmaAsync, Error> {
+        Self::new_tokio(uio)
+    }
+
+    /// Open the DMA with the reactor of the current tokio runtime and sysfs
+    /// and device nodes located under `root`.
+    ///
+    /// # Panics
+    /// If called outside of a tokio runtime with I/O enabled.
+    #[cfg(all(feature = "tokio", not(feature = "async")))]
+    pub fn with_root(uio: &str, root: &SysRoot) -> Result<AxiDmaAsync, Error> {
+        Self::new_tokio_with_root(uio, root)
+    }
+
+    /// Open the DMA whose device-tree name is `name` with the reactor used
+    /// by [`new`](Self::new).
+    ///
+    /// Use [`AxiDmaOptions::find_by_name`] for other roots, options, or
+    /// reactors.
+    pub fn find_by_name(name: &str) -> Result<AxiDmaAsync, Error> {
+        Self::new(AxiDmaOptions::new().find_by_name(name)?.uio())
+    }
+
+    /// Open the DMA whose registers are at physical address `addr` with the
+    /// reactor used by [`new`](Self::new).
+    pub fn find_by_addr(addr: usize) -> Result<AxiDmaAsync, Error> {
+        Self::new(AxiDmaOptions::new().find_by_addr(addr)?.uio())
+    }
+
+    /// List the UIO devices of the system.
+    pub fn list_devices() -> Result<Vec<UioDevice>, Error> {
+        AxiDmaOptions::new().list_devices()
+    }
+
+    fn open(uio: &str, options: &AxiDmaOptions) -> Result<(File, AxiDmaBase), Error> {
+        let dev_fd = options.open_device(uio, OpenOptions::new().custom_flags(libc::O_NONBLOCK))?;
+        let dma = AxiDmaBase::new(uio, &options.root, dev_fd.as_raw_fd())?;
+        Ok((dev_fd, dma))
+    }
+
+    /// Start a transfer. The buffer stays borrowed until the returned
+    /// [`AsyncTransfer`] is completed.
+    ///
+    /// Fails with [`Error::OutOfBounds`] if `bytes` exceeds the buffer.
+    ///
+    /// The future does not suspend, i.e., it is cancellation-safe: if it is
+    /// dropped before being polled, nothing is programmed, otherwise the
+    /// transfer is fully started.
+    pub async fn start_h2d<'a, 'b, M: DmaMemory + ?Sized>(
+        &'a mut self,
+        buff: &'b M,
+        bytes: usize,
+    ) -> Result<AsyncTransfer<'a, &'b M>, Error> {
+        self.start_h2d_now(buff, bytes)?;
+        Ok(AsyncTransfer::new(self, buff, Channel::H2d))
+    }
+
+    pub(crate) fn start_h2d_now<M: DmaMemory + ?Sized>(
+        &mut self,
+        buff: &M,
+        bytes: usize,
+    ) -> Result<(), Error> {
+        self.start_h2d_at(buff.phys_addr(), buff.len(), bytes)
+    }
+
+    fn start_h2d_at(&mut self, phys_addr: usize, len: usize, bytes: usize) -> Result<(), Error> {
+        self.dma.start_h2d_ini(len, bytes)?;
+        self.enable_uio_irqs()?;
+        self.dma.start_h2d_fini(phys_addr, bytes);
         Ok(())
     }
 
-    pub async fn start_d2h(&mut self, buff: &DmaBuffer, bytes: usize) -> Result<(), Error> {
-        self.dma.start_d2h_ini(buff, bytes);
-        self.enable_uio_irqs().await?;
-        self.dma.start_d2h_fini(buff, bytes);
+    /// Start a transfer. The buffer stays borrowed until the returned
+    /// [`AsyncTransfer`] is completed.
+    ///
+    /// Fails with [`Error::OutOfBounds`] if `bytes` exceeds the buffer.
+    ///
+    /// The future does not suspend, i.e., it is cancellation-safe: if it is
+    /// dropped before being polled, nothing is programmed, otherwise the
+    /// transfer is fully started.
+    pub async fn start_d2h<'a, 'b, M: DmaMemory + ?Sized>(
+        &'a mut self,
+        buff: &'b mut M,
+        bytes: usize,
+    ) -> Result<AsyncTransfer<'a, &'b mut M>, Error> {
+        self.start_d2h_now(&*buff, bytes)?;
+        Ok(AsyncTransfer::new(self, buff, Channel::D2h))
+    }
+
+    /// Start a transfer into memory that is only borrowed shared. The caller
+    /// makes sure that nothing accesses it until the transfer completes.
+    pub(crate) fn start_d2h_now<M: DmaMemory + ?Sized>(
+        &mut self,
+        buff: &M,
+        bytes: usize,
+    ) -> Result<(), Error> {
+        self.start_d2h_at(buff.phys_addr(), buff.len(), bytes)
+    }
+
+    fn start_d2h_at(&mut self, phys_addr: usize, len: usize, bytes: usize) -> Result<(), Error> {
+        self.dma.start_d2h_ini(len, bytes)?;
+        self.enable_uio_irqs()?;
+        self.dma.start_d2h_fini(phys_addr, bytes);
         Ok(())
     }
 
-    async fn enable_uio_irqs(&mut self) -> Result<(), Error> {
-        unsafe {
-            self.dev_fd
-                .write_with_mut(|s| s.write(&[1u8, 0, 0, 0]))
-                .await?;
-        }
+    fn enable_uio_irqs(&mut self) -> Result<(), Error> {
+        // Writing to a UIO device never blocks, so there is no need to await
+        // writability. Not having an await point between the register writes
+        // that set up a transfer keeps the operations cancellation-safe.
+        self.dev_fd.file().write_all(&[1u8, 0, 0, 0])?;
         Ok(())
     }
 
+    /// Stop a transfer that is not waited for anymore: reset the DMA if the
+    /// channel is still busy and drop its interrupt.
+    pub(super) fn abort(&mut self, channel: Channel) -> Result<(), Error> {
+        if self.dma.busy(channel.dmasr()) {
+            self.reset();
+        }
+        self.discard_irq(channel)
+    }
+
+    /// Drop the interrupt of an aborted transfer, so that it is not taken for
+    /// the completion of the next one.
+    fn discard_irq(&mut self, channel: Channel) -> Result<(), Error> {
+        // Clear the flags first, the interrupt would fire again otherwise.
+        self.dma.clear_irqs(channel.dmasr());
+        if uio_irq_pending(self.dev_fd.file())? {
+            let mut buf = [0u8; 4];
+            self.dev_fd.file().read_exact(&mut buf)?;
+        }
+        self.enable_uio_irqs()
+    }
+
     #[cfg(feature = "scatter-gather")]
     pub fn enqueue_sg_h2d(&mut self, descriptor: &mut SgDescriptor) -> Result<(), Error> {
         self.dma.enqueue_sg_h2d(descriptor)
@@ -73,42 +361,238 @@ impl AxiDmaAsync {
         self.dma.enqueue_sg_d2h(descriptor)
     }
 
+    /// Enqueue the descriptor at `index` of `ring`. Descriptors have to be
+    /// enqueued in ring order for [`AxiDmaAsync::completed_h2d`] to work.
+    ///
+    /// Fails with [`Error::DescriptorBusy`] if the descriptor was submitted
+    /// before and has not been reaped through the completions yet.
+    #[cfg(feature = "scatter-gather")]
+    pub fn enqueue_sg_ring_h2d(&mut self, ring: &mut SgRing, index: usize) -> Result<(), Error> {
+        sg_check_free(ring, index)?;
+        self.dma.enqueue_sg_h2d(ring.descriptor_mut(index))?;
+        ring.mark_submitted(index);
+        Ok(())
+    }
+
+    /// Enqueue the descriptor at `index` of `ring`, waiting for the DMA to
+    /// complete it first if it is still in flight.
+    ///
+    /// Enqueueing in ring order, this waits for a free slot in the ring. The
+    /// completions up to and including the descriptor are reaped, so they
+    /// are not reported by the completions iterator anymore. An error
+    /// reported for one of them is returned.
+    #[cfg(feature = "scatter-gather")]
+    pub async fn wait_enqueue_sg_ring_h2d(
+        &mut self,
+        ring: &mut SgRing,
+        index: usize,
+    ) -> Result<(), Error> {
+        poll_fn(|cx| self.poll_enqueue_sg_ring_h2d(cx, ring, index)).await
+    }
+
+    /// Poll-based version of [`AxiDmaAsync::wait_enqueue_sg_ring_h2d`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn poll_enqueue_sg_ring_h2d(
+        &mut self,
+        cx: &mut Context<'_>,
+        ring: &mut SgRing,
+        index: usize,
+    ) -> Poll<Result<(), Error>> {
+        if ring.is_submitted(index) {
+            ready!(self.poll_sg_complete_h2d(cx, ring.descriptor(index)))?;
+            for completed in self.completed_h2d(ring)? {
+                if completed?.0 == index {
+                    break;
+                }
+            }
+        }
+        Poll::Ready(self.enqueue_sg_ring_h2d(ring, index))
+    }
+
+    /// Enqueue the descriptor at `index` of `ring`. Descriptors have to be
+    /// enqueued in ring order for [`AxiDmaAsync::completed_d2h`] to work.
+    ///
+    /// Fails with [`Error::DescriptorBusy`] if the descriptor was submitted
+    /// before and has not been reaped through the completions yet.
+    #[cfg(feature = "scatter-gather")]
+    pub fn enqueue_sg_ring_d2h(&mut self, ring: &mut SgRing, index: usize) -> Result<(), Error> {
+        sg_check_free(ring, index)?;
+        self.dma.enqueue_sg_d2h(ring.descriptor_mut(index))?;
+        ring.mark_submitted(index);
+        Ok(())
+    }
+
+    /// Enqueue the descriptor at `index` of `ring`, waiting for the DMA to
+    /// complete it first if it is still in flight.
+    ///
+    /// Enqueueing in ring order, this waits for a free slot in the ring. The
+    /// completions up to and including the descriptor are reaped, so they
+    /// are not reported by the completions iterator anymore. An error
+    /// reported for one of them is returned.
+    #[cfg(feature = "scatter-gather")]
+    pub async fn wait_enqueue_sg_ring_d2h(
+        &mut self,
+        ring: &mut SgRing,
+        index: usize,
+    ) -> Result<(), Error> {
+        poll_fn(|cx| self.poll_enqueue_sg_ring_d2h(cx, ring, index)).await
+    }
+
+    /// Poll-based version of [`AxiDmaAsync::wait_enqueue_sg_ring_d2h`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn poll_enqueue_sg_ring_d2h(
+        &mut self,
+        cx: &mut Context<'_>,
+        ring: &mut SgRing,
+        index: usize,
+    ) -> Poll<Result<(), Error>> {
+        if ring.is_submitted(index) {
+            ready!(self.poll_sg_complete_d2h(cx, ring.descriptor(index)))?;
+            for completed in self.completed_d2h(ring)? {
+                if completed?.0 == index {
+                    break;
+                }
+            }
+        }
+        Poll::Ready(self.enqueue_sg_ring_d2h(ring, index))
+    }
+
+    /// Check whether `descriptor` has been completed without blocking.
+    ///
+    /// A pending interrupt is consumed and the interrupt is re-armed.
+    #[cfg(feature = "scatter-gather")]
+    pub fn try_complete_h2d(&mut self, descriptor: &SgDescriptor) -> Result<bool, Error> {
+        self.service_sg_irqs(MM2S_DMASR)?;
+        Ok(sg_completed(descriptor))
+    }
+
+    /// Check whether `descriptor` has been completed without blocking.
+    ///
+    /// A pending interrupt is consumed and the interrupt is re-armed.
+    #[cfg(feature = "scatter-gather")]
+    pub fn try_complete_d2h(&mut self, descriptor: &SgDescriptor) -> Result<bool, Error> {
+        self.service_sg_irqs(S2MM_DMASR)?;
+        Ok(sg_completed(descriptor))
+    }
+
+    /// Iterate over all descriptors of `ring` that completed since the last
+    /// call, in ring order.
+    #[cfg(feature = "scatter-gather")]
+    pub fn completed_h2d<'a>(
+        &'a mut self,
+        ring: &'a mut SgRing,
+    ) -> Result<SgCompletions<'a>, Error> {
+        self.service_sg_irqs(MM2S_DMASR)?;
+        Ok(SgCompletions::new(&self.dma, MM2S_DMASR, ring))
+    }
+
+    /// Iterate over all descriptors of `ring` that completed since the last
+    /// call, in ring order.
+    #[cfg(feature = "scatter-gather")]
+    pub fn completed_d2h<'a>(
+        &'a mut self,
+        ring: &'a mut SgRing,
+    ) -> Result<SgCompletions<'a>, Error> {
+        self.service_sg_irqs(S2MM_DMASR)?;
+        Ok(SgCompletions::new(&self.dma, S2MM_DMASR, ring))
+    }
+
+    /// Recover the H2D channel from an error reported in DMASR.
+    ///
+    /// See [`crate::AxiDma::recover_sg_h2d`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn recover_sg_h2d(&mut self, ring: &mut SgRing) -> Result<(), Error> {
+        self.dma.recover_sg(&MM2S_SG, ring)
+    }
+
+    /// Recover the D2H channel from an error reported in DMASR.
+    ///
+    /// See [`crate::AxiDma::recover_sg_d2h`].
+    #[cfg(feature = "scatter-gather")]
+    pub fn recover_sg_d2h(&mut self, ring: &mut SgRing) -> Result<(), Error> {
+        self.dma.recover_sg(&S2MM_SG, ring)
+    }
+
+    #[cfg(feature = "scatter-gather")]
+    fn service_sg_irqs(&mut self, dmasr: isize) -> Result<(), Error> {
+        if uio_irq_pending(self.dev_fd.file())? {
+            let mut buf = [0u8; 4];
+            self.dev_fd.file().read_exact(&mut buf)?;
+        }
+        // Clear the flags before re-arming, so that only new completions
+        // raise an interrupt.
+        self.dma.ack_sg_irqs(dmasr)?;
+        self.enable_uio_irqs()
+    }
+
+    /// Wait until `descriptor` has been completed.
+    ///
+    /// The only await point is the wait for the interrupt, so the future can
+    /// be dropped at any time. Calling the function again picks up where the
+    /// dropped future left off.
     #[cfg(feature = "scatter-gather")]
     pub async fn wait_sg_complete_h2d(&mut self, descriptor: &SgDescriptor) -> Result<(), Error> {
+        poll_fn(|cx| self.poll_sg_complete_h2d(cx, descriptor)).await
+    }
+
+    /// Poll for the completion of `descriptor`, registering the waker to be
+    /// woken on the next interrupt if it is not completed yet.
+    #[cfg(feature = "scatter-gather")]
+    pub fn poll_sg_complete_h2d(
+        &mut self,
+        cx: &mut Context<'_>,
+        descriptor: &SgDescriptor,
+    ) -> Poll<Result<(), Error>> {
         loop {
             if descriptor.completed() {
                 dmb(); // the complete flag acts as an acquire lock
-                break;
+                return Poll::Ready(Ok(()));
             }
 
             // Wait for an interrupt that might indicate that the descriptor has
             // been completed.
-            self.enable_uio_irqs().await?;
-            self.wait_h2d().await?;
+            self.enable_uio_irqs()?;
+            ready!(self.poll_irq(cx))?;
 
-            self.dma.wait_sg_complete_h2d_fini()?;
+            self.dma.ack_sg_irqs(MM2S_DMASR)?;
         }
-        Ok(())
     }
 
+    /// Wait until `descriptor` has been completed.
+    ///
+    /// The only await point is the wait for the interrupt, so the future can
+    /// be dropped at any time. Calling the function again picks up where the
+    /// dropped future left off.
     #[cfg(feature = "scatter-gather")]
     pub async fn wait_sg_complete_d2h(&mut self, descriptor: &SgDescriptor) -> Result<(), Error> {
+        poll_fn(|cx| self.poll_sg_complete_d2h(cx, descriptor)).await
+    }
+
+    /// Poll for the completion of `descriptor`, registering the waker to be
+    /// woken on the next interrupt if it is not completed yet.
+    #[cfg(feature = "scatter-gather")]
+    pub fn poll_sg_complete_d2h(
+        &mut self,
+        cx: &mut Context<'_>,
+        descriptor: &SgDescriptor,
+    ) -> Poll<Result<(), Error>> {
         loop {
             if descriptor.completed() {
                 dmb(); // the complete flag acts as an acquire lock
-                break;
+                return Poll::Ready(Ok(()));
             }
 
             // Wait for an interrupt that might indicate that the descriptor has
             // been completed.
-            self.enable_uio_irqs().await?;
-            self.wait_d2h().await?;
+            self.enable_uio_irqs()?;
+            ready!(self.poll_irq(cx))?;
 
-            self.dma.wait_sg_complete_d2h_fini()?;
+            self.dma.ack_sg_irqs(S2MM_DMASR)?;
         }
-        Ok(())
     }
 
+    /// Reset the core. This halts both channels, aborting the transfers in
+    /// either direction.
     pub fn reset(&mut self) {
         self.dma.reset();
     }
@@ -122,18 +606,377 @@ impl AxiDmaAsync {
     }
 
     pub async fn wait_d2h(&mut self) -> Result<(), Error> {
-        let mut buf = [0u8; 4];
-        unsafe { self.dev_fd.read_with_mut(|s| s.read(&mut buf)).await? };
-        Ok(())
+        poll_fn(|cx| self.poll_wait_d2h(cx)).await
     }
 
     pub async fn wait_h2d(&mut self) -> Result<(), Error> {
+        poll_fn(|cx| self.poll_wait_h2d(cx)).await
+    }
+
+    /// Poll for the completion of a register mode transfer, registering the
+    /// waker to be woken on the interrupt if it has not completed yet.
+    pub fn poll_wait_d2h(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_irq(cx)
+    }
+
+    /// Poll for the completion of a register mode transfer, registering the
+    /// waker to be woken on the interrupt if it has not completed yet.
+    pub fn poll_wait_h2d(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_irq(cx)
+    }
+
+    /// Consume an interrupt event, registering the waker if there is none.
+    pub(crate) fn poll_irq(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         let mut buf = [0u8; 4];
-        unsafe { self.dev_fd.read_with_mut(|s| s.read(&mut buf)).await? };
-        Ok(())
+        loop {
+            match self.dev_fd.file().read(&mut buf) {
+                Ok(_) => return Poll::Ready(Ok(())),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+            ready!(self.dev_fd.poll_irq_pending(cx))?;
+        }
+    }
+
+    /// Register the waker to be woken on the next interrupt event without
+    /// consuming it.
+    #[cfg(feature = "scatter-gather")]
+    pub(crate) fn poll_irq_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.dev_fd.poll_irq_pending(cx)
+    }
+
+    pub(crate) fn check_errors_h2d(&self) -> Result<(), Error> {
+        self.dma.check_errors(self.dma.status(MM2S_DMASR))
+    }
+
+    pub(crate) fn check_errors_d2h(&self) -> Result<(), Error> {
+        self.dma.check_errors(self.dma.status(S2MM_DMASR))
     }
 
     pub fn size_d2h(&self) -> usize {
         self.dma.size_d2h()
     }
+
+    /// Physical address of the descriptor the H2D channel is working on.
+    /// Use [`SgRing::index_of`] to map it to a descriptor of a ring.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_descriptor_h2d(&self) -> u64 {
+        self.dma
+            .descriptor_register(MM2S_CURRDESC, MM2S_CURRDESC_MSB)
+    }
+
+    /// Physical address of the last descriptor enqueued on the H2D channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_descriptor_h2d(&self) -> u64 {
+        self.dma
+            .descriptor_register(MM2S_TAILDESC, MM2S_TAILDESC_MSB)
+    }
+
+    /// Physical address of the descriptor the D2H channel is working on.
+    /// Use [`SgRing::index_of`] to map it to a descriptor of a ring.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_descriptor_d2h(&self) -> u64 {
+        self.dma
+            .descriptor_register(S2MM_CURRDESC, S2MM_CURRDESC_MSB)
+    }
+
+    /// Physical address of the last descriptor enqueued on the D2H channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_descriptor_d2h(&self) -> u64 {
+        self.dma
+            .descriptor_register(S2MM_TAILDESC, S2MM_TAILDESC_MSB)
+    }
+
+    /// Index of the descriptor of `ring` the H2D channel is working on.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_index_h2d(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.current_descriptor_h2d())
+    }
+
+    /// Index of the last descriptor of `ring` enqueued on the H2D channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_index_h2d(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.tail_descriptor_h2d())
+    }
+
+    /// Index of the descriptor of `ring` the D2H channel is working on.
+    #[cfg(feature = "scatter-gather")]
+    pub fn current_index_d2h(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.current_descriptor_d2h())
+    }
+
+    /// Index of the last descriptor of `ring` enqueued on the D2H channel.
+    #[cfg(feature = "scatter-gather")]
+    pub fn tail_index_d2h(&self, ring: &SgRing) -> Option<usize> {
+        ring_index(ring, self.tail_descriptor_d2h())
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use std::pin::pin;
+
+    use super::*;
+    use crate::axi_dma::fake::{noop_waker, poll_once, FakeDma, ResetEmulator};
+    use crate::axi_dma::{MM2S_DMACR, MM2S_LENGTH};
+
+    struct Memory(Vec<u8>);
+
+    unsafe impl DmaMemory for Memory {
+        fn phys_addr(&self) -> usize {
+            self.0.as_ptr() as usize
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn as_ptr(&self) -> *mut libc::c_void {
+            self.0.as_ptr() as *mut libc::c_void
+        }
+    }
+
+    #[test]
+    fn start_dropped_before_poll() {
+        let mut f = FakeDma::new("start_dropped");
+        let mut dma = f.dma_async();
+        let memory = Memory(vec![0; 64]);
+        drop(dma.start_h2d(&memory, 16));
+        assert_eq!(f.regs.get(MM2S_LENGTH), 0);
+        assert_eq!(f.enables(), 0);
+    }
+
+    #[test]
+    fn start_completes_in_one_poll() {
+        let mut f = FakeDma::new("start_one_poll");
+        let mut dma = f.dma_async();
+        let memory = Memory(vec![0; 64]);
+        let transfer = match poll_once(pin!(dma.start_h2d(&memory, 16))) {
+            Poll::Ready(Ok(transfer)) => transfer,
+            _ => panic!("start did not complete in one poll"),
+        };
+        assert_eq!(f.regs.get(MM2S_LENGTH), 16);
+        assert_eq!(f.enables(), 1);
+        // Idle, so that dropping the transfer does not reset.
+        f.regs.set(MM2S_DMASR, 0x2);
+        drop(transfer);
+    }
+
+    #[test]
+    fn wait_dropped_and_resumed() {
+        let mut f = FakeDma::new("wait_resumed");
+        let mut dma = f.dma_async();
+        assert!(poll_once(pin!(dma.wait_h2d())).is_pending());
+        assert!(poll_once(pin!(dma.wait_h2d())).is_pending());
+        f.raise_irq();
+        assert!(matches!(
+            poll_once(pin!(dma.wait_h2d())),
+            Poll::Ready(Ok(()))
+        ));
+        // The event has been consumed.
+        assert!(poll_once(pin!(dma.wait_h2d())).is_pending());
+    }
+
+    #[test]
+    fn transfer_dropped_while_waiting() {
+        let mut f = FakeDma::new("transfer_dropped");
+        let mut dma = f.dma_async();
+        let _emulator = ResetEmulator::new(&f.regs);
+        let memory = Memory(vec![0; 64]);
+        f.regs.set(S2MM_DMASR, 0x1);
+        {
+            let transfer = match poll_once(pin!(dma.start_h2d(&memory, 16))) {
+                Poll::Ready(Ok(transfer)) => transfer,
+                _ => panic!("start did not complete in one poll"),
+            };
+            // The transfer is running, so dropping the wait aborts it.
+            assert!(poll_once(pin!(transfer.wait())).is_pending());
+        }
+        // A reset clears the interrupt flags of both channels.
+        assert_eq!(f.regs.get(S2MM_DMASR), 0x7000);
+        assert_eq!(f.regs.get(MM2S_DMACR) & 0x4, 0);
+    }
+
+    #[test]
+    fn transfer_dropped_after_completion() {
+        let mut f = FakeDma::new("transfer_stale_irq");
+        let mut dma = f.dma_async();
+        let memory = Memory(vec![0; 64]);
+        let transfer = match poll_once(pin!(dma.start_h2d(&memory, 16))) {
+            Poll::Ready(Ok(transfer)) => transfer,
+            _ => panic!("start did not complete in one poll"),
+        };
+        // Completed, but the interrupt has not been consumed.
+        f.regs.set(MM2S_DMASR, 0x2);
+        f.raise_irq();
+        drop(transfer);
+        // The interrupt was discarded and re-armed.
+        assert_eq!(f.enables(), 2);
+        assert!(poll_once(pin!(dma.wait_h2d())).is_pending());
+    }
+
+    #[test]
+    fn transfer_completed_after_resume() {
+        let mut f = FakeDma::new("transfer_resumed");
+        let mut dma = f.dma_async();
+        let memory = Memory(vec![0; 64]);
+        let mut transfer = match poll_once(pin!(dma.start_h2d(&memory, 16))) {
+            Poll::Ready(Ok(transfer)) => transfer,
+            _ => panic!("start did not complete in one poll"),
+        };
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(transfer.poll_wait(&mut cx).is_pending());
+        f.raise_irq();
+        assert!(matches!(transfer.poll_wait(&mut cx), Poll::Ready(Ok(()))));
+        // Completed, so dropping it leaves the DMA alone.
+        let dmacr = f.regs.get(MM2S_DMACR);
+        drop(transfer);
+        assert_eq!(f.regs.get(MM2S_DMACR), dmacr);
+    }
+
+    #[cfg(feature = "scatter-gather")]
+    mod sg {
+        use std::alloc::{alloc_zeroed, dealloc, Layout};
+
+        use super::*;
+        use crate::axi_dma::{MM2S_CURRDESC, MM2S_CURRDESC_MSB, MM2S_TAILDESC};
+        use crate::SG_DESCRIPTOR_LEN;
+
+        /// Descriptors in heap memory, with their virtual address as
+        /// physical address.
+        struct Descriptors {
+            ptr: *mut u8,
+            layout: Layout,
+        }
+
+        impl Descriptors {
+            fn new(count: usize) -> Descriptors {
+                let layout = Layout::from_size_align(count * SG_DESCRIPTOR_LEN, 64).unwrap();
+                let ptr = unsafe { alloc_zeroed(layout) };
+                Descriptors { ptr, layout }
+            }
+
+            fn ring(&self) -> SgRing {
+                let count = self.layout.size() / SG_DESCRIPTOR_LEN;
+                SgRing::new(
+                    (0..count)
+                        .map(|i| unsafe {
+                            let base = self.ptr.add(i * SG_DESCRIPTOR_LEN);
+                            SgDescriptor::from_base_ptr(base as *mut u32, base as usize)
+                        })
+                        .collect(),
+                )
+            }
+        }
+
+        impl Drop for Descriptors {
+            fn drop(&mut self) {
+                unsafe { dealloc(self.ptr, self.layout) }
+            }
+        }
+
+        #[test]
+        fn wait_sg_complete_resumed() {
+            let mut f = FakeDma::new("sg_complete_resumed");
+            let mut dma = f.dma_async();
+            let descriptors = Descriptors::new(2);
+            let ring = descriptors.ring();
+            let descriptor = ring.descriptor(0);
+            assert!(poll_once(pin!(dma.wait_sg_complete_h2d(descriptor))).is_pending());
+            assert!(poll_once(pin!(dma.wait_sg_complete_h2d(descriptor))).is_pending());
+            descriptor.set_completed(true);
+            f.raise_irq();
+            assert!(matches!(
+                poll_once(pin!(dma.wait_sg_complete_h2d(descriptor))),
+                Poll::Ready(Ok(()))
+            ));
+        }
+
+        #[test]
+        fn wait_enqueue_dropped() {
+            let mut f = FakeDma::new("sg_enqueue_dropped");
+            let mut dma = f.dma_async();
+            let descriptors = Descriptors::new(2);
+            let mut ring = descriptors.ring();
+            // Halted, with scatter gather included.
+            f.regs.set(MM2S_DMASR, 0x9);
+            dma.enqueue_sg_ring_h2d(&mut ring, 0).unwrap();
+            let tail = f.regs.get(MM2S_TAILDESC);
+
+            assert!(poll_once(pin!(dma.wait_enqueue_sg_ring_h2d(&mut ring, 0))).is_pending());
+            assert!(ring.is_submitted(0));
+            assert_eq!(ring.next_completion(), 0);
+            assert_eq!(f.regs.get(MM2S_TAILDESC), tail);
+
+            // Completed descriptors are only free once they are reaped.
+            ring.descriptor(0).set_completed(true);
+            f.raise_irq();
+            assert!(matches!(
+                dma.enqueue_sg_ring_h2d(&mut ring, 0),
+                Err(Error::DescriptorBusy(0))
+            ));
+            let reaped: Vec<usize> = dma
+                .completed_h2d(&mut ring)
+                .unwrap()
+                .map(|c| c.unwrap().0)
+                .collect();
+            assert_eq!(reaped, [0]);
+
+            // The fake DMASR does not keep its bits when the interrupts are
+            // acknowledged.
+            f.regs.set(MM2S_DMASR, 0x9);
+            assert!(matches!(
+                poll_once(pin!(dma.wait_enqueue_sg_ring_h2d(&mut ring, 0))),
+                Poll::Ready(Ok(()))
+            ));
+            assert!(ring.is_submitted(0));
+            assert!(!ring.descriptor(0).completed());
+        }
+
+        #[test]
+        fn recover_skips_faulting_descriptor() {
+            let mut f = FakeDma::new("sg_recover");
+            let _emulator = ResetEmulator::new(&f.regs);
+            let mut dma = f.dma_async();
+            let descriptors = Descriptors::new(3);
+            let mut ring = descriptors.ring();
+            f.regs.set(MM2S_DMASR, 0x9);
+            for index in 0..3 {
+                dma.enqueue_sg_ring_h2d(&mut ring, index).unwrap();
+            }
+
+            // Descriptor 0 completed, the DMA failed on 1 and halted.
+            ring.descriptor(0).set_completed(true);
+            ring.descriptor_mut(2).set_completed(true);
+            let faulting = ring.descriptor(1).phys_addr() as u64;
+            f.regs.set(MM2S_CURRDESC, (faulting & 0xffff_ffff) as u32);
+            f.regs.set(MM2S_CURRDESC_MSB, (faulting >> 32) as u32);
+            f.regs.set(MM2S_DMASR, 0x29);
+            assert!(matches!(
+                dma.recover_sg_h2d(&mut ring),
+                Err(Error::SgFault { phys_addr, dmasr: 0x29, .. }) if phys_addr == faulting as usize
+            ));
+            // The descriptors after the faulting one are run again.
+            assert!(!ring.descriptor(2).completed());
+            let next = ring.descriptor(2).phys_addr() as u64;
+            assert_eq!(f.regs.get(MM2S_CURRDESC), (next & 0xffff_ffff) as u32);
+            assert_eq!(f.regs.get(MM2S_CURRDESC_MSB), (next >> 32) as u32);
+
+            // The fault is reported in place of the completion, in ring order.
+            f.regs.set(MM2S_DMASR, 0x8);
+            let completions: Vec<Result<usize, Error>> = dma
+                .completed_h2d(&mut ring)
+                .unwrap()
+                .map(|c| c.map(|(index, _)| index))
+                .collect();
+            assert!(matches!(
+                completions[..],
+                [Ok(0), Err(Error::SgFault { phys_addr, .. })] if phys_addr == faulting as usize
+            ));
+            assert!(!ring.is_submitted(1));
+            assert!(ring.is_submitted(2));
+            assert_eq!(ring.next_completion(), 2);
+        }
+    }
 }
@@ -0,0 +1,286 @@
+use std::fmt;
+use std::io;
+use std::io::Read;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::pin::Pin;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::task::{ready, Context, Poll};
+
+use super::AxiDma;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use super::AxiDmaAsync;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use super::Channel;
+use crate::DmaBuffer;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use crate::Error;
+
+/// [`Read`] adapter that receives data through S2MM.
+///
+/// Each transfer receives one packet of at most `transfer_len` bytes into a
+/// DMA buffer. A single `read` never returns data of more than one packet.
+pub struct D2hReader {
+    dma: AxiDma,
+    buffer: DmaBuffer,
+    transfer_len: usize,
+    pos: usize,
+    len: usize,
+}
+
+impl fmt::Debug for D2hReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "D2hReader ({})", self.buffer.name())?;
+        writeln!(f, "  transfer_len: {:#x?}", &self.transfer_len)?;
+        write!(f, "  buffered: {:#x?}", self.len - self.pos)
+    }
+}
+
+impl D2hReader {
+    pub fn new(dma: AxiDma, buffer: DmaBuffer, transfer_len: usize) -> D2hReader {
+        assert!(transfer_len > 0 && transfer_len <= buffer.size());
+        D2hReader {
+            dma,
+            buffer,
+            transfer_len,
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Whether the last read consumed the remainder of a packet.
+    pub fn at_packet_boundary(&self) -> bool {
+        self.pos == self.len
+    }
+
+    /// Give back the DMA and the buffer. Data not read yet is dropped.
+    pub fn into_inner(self) -> (AxiDma, DmaBuffer) {
+        (self.dma, self.buffer)
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        let transfer_len = self.transfer_len;
+        self.dma.start_d2h(&mut self.buffer, transfer_len)?.wait()?;
+        self.len = self.dma.size_d2h();
+        self.pos = 0;
+        self.buffer
+            .sync_for_cpu_range(0, self.len, crate::SyncDirection::FromDevice)?;
+        Ok(())
+    }
+}
+
+impl Read for D2hReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.pos == self.len {
+            self.receive()?;
+        }
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.buffer.as_slice::<u8>()[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Async version of [`D2hReader`], implementing
+/// [`AsyncRead`](futures_io::AsyncRead).
+///
+/// Dropping the reader while a transfer is in flight resets the DMA.
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub struct AsyncD2hReader {
+    // Only `None` once taken by `into_inner`.
+    dma: Option<AxiDmaAsync>,
+    buffer: Option<DmaBuffer>,
+    transfer_len: usize,
+    pos: usize,
+    len: usize,
+    in_flight: bool,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl fmt::Debug for AsyncD2hReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "AsyncD2hReader ({})", self.buffer().name())?;
+        writeln!(f, "  transfer_len: {:#x?}", &self.transfer_len)?;
+        writeln!(f, "  buffered: {:#x?}", self.len - self.pos)?;
+        write!(f, "  in_flight: {:?}", &self.in_flight)
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl AsyncD2hReader {
+    pub fn new(dma: AxiDmaAsync, buffer: DmaBuffer, transfer_len: usize) -> AsyncD2hReader {
+        assert!(transfer_len > 0 && transfer_len <= buffer.size());
+        AsyncD2hReader {
+            dma: Some(dma),
+            buffer: Some(buffer),
+            transfer_len,
+            pos: 0,
+            len: 0,
+            in_flight: false,
+        }
+    }
+
+    /// Whether the last read consumed the remainder of a packet.
+    pub fn at_packet_boundary(&self) -> bool {
+        self.pos == self.len
+    }
+
+    /// Abort the transfer in flight, if any, and give back the DMA and the
+    /// buffer. Data not read yet is dropped.
+    pub fn into_inner(mut self) -> Result<(AxiDmaAsync, DmaBuffer), Error> {
+        self.stop()?;
+        Ok((self.dma.take().unwrap(), self.buffer.take().unwrap()))
+    }
+
+    fn buffer(&self) -> &DmaBuffer {
+        self.buffer.as_ref().unwrap()
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        if !self.in_flight {
+            return Ok(());
+        }
+        self.in_flight = false;
+        self.dma.as_mut().unwrap().abort(Channel::D2h)
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl futures_io::AsyncRead for AsyncD2hReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let (dma, buffer) = (this.dma.as_mut().unwrap(), this.buffer.as_ref().unwrap());
+        while this.pos == this.len {
+            if !this.in_flight {
+                dma.start_d2h_now(buffer, this.transfer_len)?;
+                this.in_flight = true;
+            }
+            ready!(dma.poll_wait_d2h(cx))?;
+            this.in_flight = false;
+            this.len = dma.size_d2h();
+            this.pos = 0;
+            buffer.sync_for_cpu_range(0, this.len, crate::SyncDirection::FromDevice)?;
+        }
+        let n = buf.len().min(this.len - this.pos);
+        buf[..n].copy_from_slice(&buffer.as_slice::<u8>()[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl Drop for AsyncD2hReader {
+    fn drop(&mut self) {
+        if self.dma.is_some() {
+            let _ = self.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axi_dma::fake::FakeDma;
+    use crate::axi_dma::S2MM_LENGTH;
+
+    fn buffer(f: &FakeDma) -> DmaBuffer {
+        let mut buffer = f.buffer("udmabuf0", 4096);
+        for (i, byte) in buffer.as_mut_slice::<u8>().iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        buffer
+    }
+
+    #[test]
+    fn reads_do_not_cross_packets() {
+        let mut f = FakeDma::new("d2h_reader_packets");
+        let buffer = buffer(&f);
+        let mut reader = D2hReader::new(f.dma(), buffer, 64);
+        let mut buf = [0u8; 100];
+
+        f.raise_irq();
+        assert_eq!(reader.read(&mut buf[..16]).unwrap(), 16);
+        assert_eq!(f.regs.get(S2MM_LENGTH), 64);
+        assert!(!reader.at_packet_boundary());
+        // The rest of the packet only, even though the buffer is larger.
+        assert_eq!(reader.read(&mut buf).unwrap(), 48);
+        assert_eq!(buf[..48], (16..64).collect::<Vec<u8>>()[..]);
+        assert!(reader.at_packet_boundary());
+
+        // The next read receives the next packet.
+        f.raise_irq();
+        assert_eq!(reader.read(&mut buf).unwrap(), 64);
+        assert_eq!(buf[..64], (0..64).collect::<Vec<u8>>()[..]);
+        assert!(reader.at_packet_boundary());
+    }
+
+    #[cfg(feature = "async")]
+    mod async_reader {
+        use std::task::Context;
+
+        use futures_io::AsyncRead;
+
+        use super::*;
+        use crate::axi_dma::fake::{noop_waker, ResetEmulator};
+        use crate::axi_dma::MM2S_DMASR;
+
+        fn poll_read(reader: &mut AsyncD2hReader, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            let waker = noop_waker();
+            Pin::new(reader).poll_read(&mut Context::from_waker(&waker), buf)
+        }
+
+        #[test]
+        fn short_packet() {
+            let mut f = FakeDma::new("async_d2h_reader_packets");
+            let buffer = buffer(&f);
+            let mut reader = AsyncD2hReader::new(f.dma_async(), buffer, 64);
+            let mut buf = [0u8; 16];
+
+            assert!(poll_read(&mut reader, &mut buf).is_pending());
+            assert_eq!(f.regs.get(S2MM_LENGTH), 64);
+            // The packet ends before the transfer length.
+            f.regs.set(S2MM_LENGTH, 10);
+            f.raise_irq();
+            assert!(matches!(
+                poll_read(&mut reader, &mut buf),
+                Poll::Ready(Ok(10))
+            ));
+            assert_eq!(buf[..10], (0..10).collect::<Vec<u8>>()[..]);
+            assert!(reader.at_packet_boundary());
+
+            f.raise_irq();
+            assert!(matches!(
+                poll_read(&mut reader, &mut buf),
+                Poll::Ready(Ok(16))
+            ));
+            assert!(!reader.at_packet_boundary());
+        }
+
+        #[test]
+        fn into_inner_aborts_transfer() {
+            let mut f = FakeDma::new("async_d2h_reader_into_inner");
+            let _emulator = ResetEmulator::new(&f.regs);
+            let buffer = buffer(&f);
+            let mut reader = AsyncD2hReader::new(f.dma_async(), buffer, 64);
+            assert!(poll_read(&mut reader, &mut [0u8; 16]).is_pending());
+            assert_eq!(f.enables(), 1);
+            f.regs.set(MM2S_DMASR, 0x1);
+
+            let (_dma, buffer) = reader.into_inner().unwrap();
+            assert_eq!(buffer.name(), "udmabuf0");
+            // The reset clears the flags of both channels, and the interrupt
+            // is re-armed.
+            assert_eq!(f.regs.get(MM2S_DMASR), 0x7000);
+            assert_eq!(f.enables(), 1);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use super::slots::Slots;
+use super::Channel;
+#[cfg(feature = "scatter-gather")]
+use crate::scatter_gather::buffer_length;
+use crate::AxiDmaAsync;
+use crate::DmaBuffer;
+use crate::Error;
+#[cfg(feature = "scatter-gather")]
+use crate::SgRing;
+use crate::SyncDirection;
+
+/// Stream of buffers received on the D2H channel.
+///
+/// The stream owns a set of buffers and keeps the channel armed with every
+/// buffer that is not held by the application. Received buffers are yielded as
+/// [`D2hBuffer`] guards and re-armed once the guard is dropped. In register
+/// mode, the next free buffer is armed as soon as a transfer completes. In
+/// scatter gather mode, all free buffers are queued in a descriptor ring.
+///
+/// After an error, the stream terminates. Once it terminates or is dropped,
+/// transfers of armed buffers are aborted, resetting the DMA if they are still
+/// running.
+pub struct D2hStream {
+    // Only `None` once taken by `into_inner`.
+    dma: Option<AxiDmaAsync>,
+    slots: Arc<Slots>,
+    transfer_len: usize,
+    armed: VecDeque<usize>,
+    #[cfg(feature = "scatter-gather")]
+    ring: Option<SgRing>,
+    #[cfg(feature = "scatter-gather")]
+    head: usize,
+    terminated: bool,
+}
+
+impl fmt::Debug for D2hStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "D2hStream")?;
+        writeln!(f, "  buffers: {}", self.slots.len())?;
+        writeln!(f, "  transfer_len: {:#x?}", &self.transfer_len)?;
+        write!(f, "  armed: {:?}", &self.armed)
+    }
+}
+
+impl D2hStream {
+    /// Create a stream that receives `transfer_len` bytes per buffer with
+    /// register mode transfers.
+    pub fn new(dma: AxiDmaAsync, buffers: Vec<DmaBuffer>, transfer_len: usize) -> D2hStream {
+        assert!(!buffers.is_empty());
+        assert!(buffers.iter().all(|b| b.size() >= transfer_len));
+        D2hStream {
+            dma: Some(dma),
+            slots: Arc::new(Slots::new(buffers)),
+            transfer_len,
+            armed: VecDeque::new(),
+            #[cfg(feature = "scatter-gather")]
+            ring: None,
+            #[cfg(feature = "scatter-gather")]
+            head: 0,
+            terminated: false,
+        }
+    }
+
+    /// Create a stream that receives `transfer_len` bytes per buffer with
+    /// scatter gather transfers. Descriptor `i` of `ring` is used for
+    /// `buffers[i]`.
+    ///
+    /// Fails with [`Error::TooLong`] if `transfer_len` does not fit in a
+    /// descriptor.
+    #[cfg(feature = "scatter-gather")]
+    pub fn with_ring(
+        dma: AxiDmaAsync,
+        mut ring: SgRing,
+        buffers: Vec<DmaBuffer>,
+        transfer_len: usize,
+    ) -> Result<D2hStream, Error> {
+        assert_eq!(ring.len(), buffers.len());
+        let length = buffer_length(transfer_len)?;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let descriptor = ring.descriptor_mut(i);
+            descriptor.set_buffer_address(buffer.phys_addr());
+            descriptor.set_buffer_length(length);
+        }
+        let mut stream = D2hStream::new(dma, buffers, transfer_len);
+        stream.ring = Some(ring);
+        Ok(stream)
+    }
+
+    pub fn transfer_len(&self) -> usize {
+        self.transfer_len
+    }
+
+    /// Abort the transfers of the armed buffers and give back the DMA. Buffers
+    /// are released once the last [`D2hBuffer`] is dropped.
+    pub fn into_inner(mut self) -> Result<AxiDmaAsync, Error> {
+        self.stop()?;
+        Ok(self.dma.take().unwrap())
+    }
+
+    /// Abort the transfers of the armed buffers and put them back into
+    /// rotation.
+    fn stop(&mut self) -> Result<(), Error> {
+        if self.armed.is_empty() {
+            return Ok(());
+        }
+        let res = self.dma.as_mut().unwrap().abort(Channel::D2h);
+        for index in self.armed.drain(..) {
+            self.slots.release(index);
+        }
+        res
+    }
+
+    /// Queue free buffers on the channel.
+    fn arm(&mut self) -> Result<(), Error> {
+        let dma = self.dma.as_mut().unwrap();
+        #[cfg(feature = "scatter-gather")]
+        if let Some(ring) = self.ring.as_mut() {
+            // The DMA follows the descriptor chain, so buffers have to be
+            // queued in ring order.
+            while self.slots.take_index(self.head) {
+                dma.enqueue_sg_ring_d2h(ring, self.head)?;
+                self.armed.push_back(self.head);
+                self.head = (self.head + 1) % ring.len();
+            }
+            return Ok(());
+        }
+
+        if self.armed.is_empty() {
+            if let Some(index) = self.slots.take() {
+                dma.start_d2h_now(self.slots.buffer(index), self.transfer_len)?;
+                self.armed.push_back(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the oldest armed buffer to complete, returning the number of
+    /// bytes received.
+    fn poll_complete(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, Error>> {
+        let dma = self.dma.as_mut().unwrap();
+        #[cfg(feature = "scatter-gather")]
+        if let Some(ring) = self.ring.as_mut() {
+            loop {
+                if let Some(completed) = dma.completed_d2h(ring)?.next() {
+                    let (index, descriptor) = completed?;
+                    debug_assert_eq!(Some(&index), self.armed.front());
+                    return Poll::Ready(Ok(descriptor.transferred_bytes() as usize));
+                }
+                ready!(dma.poll_irq_pending(cx))?;
+            }
+        }
+
+        ready!(dma.poll_irq(cx))?;
+        dma.check_errors_d2h()?;
+        Poll::Ready(Ok(dma.size_d2h()))
+    }
+
+    fn poll_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<D2hBuffer, Error>> {
+        // Get woken when the application releases a buffer, so that it can be
+        // re-armed right away.
+        self.slots.register(cx);
+        self.arm()?;
+        if self.armed.is_empty() {
+            return Poll::Pending;
+        }
+
+        let len = ready!(self.poll_complete(cx))?;
+        let index = self.armed.pop_front().unwrap();
+        self.arm()?;
+        let buffer = D2hBuffer {
+            slots: self.slots.clone(),
+            index,
+            len,
+        };
+        // Dropping the guard on error puts the buffer back into rotation.
+        buffer
+            .buffer()
+            .sync_for_cpu_range(0, len, SyncDirection::FromDevice)?;
+        Poll::Ready(Ok(buffer))
+    }
+}
+
+impl Stream for D2hStream {
+    type Item = Result<D2hBuffer, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.terminated {
+            return Poll::Ready(None);
+        }
+        let res = ready!(this.poll_buffer(cx));
+        if res.is_err() {
+            this.terminated = true;
+            let _ = this.stop();
+        }
+        Poll::Ready(Some(res))
+    }
+}
+
+impl Drop for D2hStream {
+    fn drop(&mut self) {
+        if self.dma.is_some() {
+            let _ = self.stop();
+        }
+    }
+}
+
+/// A buffer received by a [`D2hStream`].
+///
+/// Dereferences to the received bytes, which have been synced for the CPU.
+/// The buffer is re-armed once the guard is dropped.
+pub struct D2hBuffer {
+    slots: Arc<Slots>,
+    index: usize,
+    len: usize,
+}
+
+impl D2hBuffer {
+    /// The underlying DMA buffer.
+    pub fn buffer(&self) -> &DmaBuffer {
+        self.slots.buffer(self.index)
+    }
+}
+
+impl fmt::Debug for D2hBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "D2hBuffer ({})", self.buffer().name())?;
+        write!(f, "  len: {:#x?}", &self.len)
+    }
+}
+
+impl Deref for D2hBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer().as_slice::<u8>()[..self.len]
+    }
+}
+
+impl Drop for D2hBuffer {
+    fn drop(&mut self) {
+        self.slots.release(self.index);
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use std::pin::pin;
+
+    use super::*;
+    use crate::axi_dma::fake::{noop_waker, poll_once, FakeDma, ResetEmulator};
+    use crate::axi_dma::{MM2S_DMASR, S2MM_DMASR, S2MM_LENGTH};
+
+    fn poll_next(stream: &mut D2hStream) -> Poll<Option<Result<D2hBuffer, Error>>> {
+        let waker = noop_waker();
+        Pin::new(stream).poll_next(&mut Context::from_waker(&waker))
+    }
+
+    fn stream(f: &mut FakeDma) -> D2hStream {
+        let dma = f.dma_async();
+        D2hStream::new(dma, vec![f.buffer("udmabuf0", 256)], 64)
+    }
+
+    #[test]
+    fn yields_received_bytes() {
+        let mut f = FakeDma::new("stream_yields");
+        let _emulator = ResetEmulator::new(&f.regs);
+        let mut stream = stream(&mut f);
+        assert!(poll_next(&mut stream).is_pending());
+        assert_eq!(f.regs.get(S2MM_LENGTH), 64);
+
+        f.regs.set(S2MM_DMASR, 0x2);
+        f.regs.set(S2MM_LENGTH, 48);
+        f.raise_irq();
+        let buffer = match poll_next(&mut stream) {
+            Poll::Ready(Some(Ok(buffer))) => buffer,
+            _ => panic!("no buffer received"),
+        };
+        assert_eq!(buffer.len(), 48);
+        // The only buffer is held by the application.
+        assert!(stream.armed.is_empty());
+        drop(buffer);
+        assert!(poll_next(&mut stream).is_pending());
+        assert_eq!(stream.armed.len(), 1);
+    }
+
+    #[test]
+    fn dropped_while_armed() {
+        let mut f = FakeDma::new("stream_dropped");
+        let _emulator = ResetEmulator::new(&f.regs);
+        let mut stream = stream(&mut f);
+        assert!(poll_next(&mut stream).is_pending());
+        f.regs.set(MM2S_DMASR, 0x1);
+        drop(stream);
+        // The reset clears the flags of both channels.
+        assert_eq!(f.regs.get(MM2S_DMASR), 0x7000);
+    }
+
+    #[test]
+    fn into_inner_after_completion() {
+        let mut f = FakeDma::new("stream_into_inner");
+        let mut stream = stream(&mut f);
+        assert!(poll_next(&mut stream).is_pending());
+        assert_eq!(f.enables(), 1);
+
+        // Completed, but not received by the stream.
+        f.regs.set(MM2S_DMASR, 0x1);
+        f.regs.set(S2MM_DMASR, 0x2);
+        f.raise_irq();
+        let mut dma = stream.into_inner().unwrap();
+        // Not reset, but the interrupt was discarded and re-armed.
+        assert_eq!(f.regs.get(MM2S_DMASR), 0x1);
+        assert_eq!(f.enables(), 1);
+        assert!(poll_once(pin!(dma.wait_d2h())).is_pending());
+    }
+
+    #[test]
+    fn terminates_on_error() {
+        let mut f = FakeDma::new("stream_error");
+        let mut stream = stream(&mut f);
+        assert!(poll_next(&mut stream).is_pending());
+
+        // Halted with a slave error.
+        f.regs.set(S2MM_DMASR, 0x21);
+        f.raise_irq();
+        assert!(matches!(
+            poll_next(&mut stream),
+            Poll::Ready(Some(Err(Error::DmaSlave(_))))
+        ));
+        assert!(stream.armed.is_empty());
+        assert!(matches!(poll_next(&mut stream), Poll::Ready(None)));
+    }
+}
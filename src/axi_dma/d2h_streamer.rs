@@ -0,0 +1,322 @@
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::AxiDma;
+use crate::DmaBufferPool;
+use crate::DmaMemory;
+use crate::Error;
+use crate::PooledBuffer;
+
+/// How often the worker checks whether it should stop.
+const STOP_POLL: Duration = Duration::from_millis(10);
+
+/// Continuous register mode reception into the buffers of a pool.
+///
+/// A worker thread re-arms S2MM with the next free buffer right after a
+/// transfer completed and hands the completed buffer to the consumer. If the
+/// consumer holds on to all buffers, S2MM cannot be re-armed, which is counted
+/// as an overrun. The worker then waits for the consumer to drop a buffer.
+pub struct D2hStreamer {
+    stop: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+    thread: Option<thread::JoinHandle<Result<AxiDma, Error>>>,
+}
+
+/// Statistics of a [`D2hStreamer`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct D2hStats {
+    pub transfers: u64,
+    pub bytes: u64,
+    pub overruns: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    transfers: AtomicU64,
+    bytes: AtomicU64,
+    overruns: AtomicU64,
+}
+
+impl fmt::Debug for D2hStreamer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "D2hStreamer")?;
+        write!(f, "  stats: {:?}", self.stats())
+    }
+}
+
+impl D2hStreamer {
+    /// Start streaming transfers of `transfer_len` bytes. Completed buffers
+    /// are delivered through the returned channel.
+    ///
+    /// The worker stops with [`Error::OutOfBounds`] if `transfer_len` exceeds
+    /// a buffer of the pool.
+    pub fn start(
+        dma: AxiDma,
+        pool: DmaBufferPool,
+        transfer_len: usize,
+    ) -> (D2hStreamer, mpsc::Receiver<D2hChunk>) {
+        let (tx, rx) = mpsc::channel();
+        let streamer = D2hStreamer::start_with_callback(dma, pool, transfer_len, move |chunk| {
+            // The consumer might have dropped the receiver, in which case the
+            // buffer just goes back to the pool.
+            let _ = tx.send(chunk);
+        });
+        (streamer, rx)
+    }
+
+    /// Start streaming transfers of `transfer_len` bytes. `callback` is called
+    /// from the worker thread for each completed buffer.
+    pub fn start_with_callback<F>(
+        dma: AxiDma,
+        pool: DmaBufferPool,
+        transfer_len: usize,
+        callback: F,
+    ) -> D2hStreamer
+    where
+        F: FnMut(D2hChunk) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(Counters::default());
+        let worker = Worker {
+            dma,
+            pool,
+            transfer_len,
+            stop: stop.clone(),
+            counters: counters.clone(),
+        };
+        let thread = thread::spawn(move || worker.run(callback));
+        D2hStreamer {
+            stop,
+            counters,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn stats(&self) -> D2hStats {
+        D2hStats {
+            transfers: self.counters.transfers.load(Ordering::Relaxed),
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            overruns: self.counters.overruns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the worker stopped, e.g., because of an error.
+    pub fn is_finished(&self) -> bool {
+        match &self.thread {
+            Some(thread) => thread.is_finished(),
+            None => true,
+        }
+    }
+
+    /// Stop streaming, aborting the transfer in flight, and give back the DMA.
+    /// Returns the error that stopped the worker, if any, or an I/O error if
+    /// the worker panicked.
+    pub fn stop(mut self) -> Result<AxiDma, Error> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<AxiDma, Error> {
+        self.stop.store(true, Ordering::Relaxed);
+        let thread = self.thread.take().expect("worker already joined");
+        thread
+            .join()
+            .map_err(|_| io::Error::other("D2H streamer thread panicked"))?
+    }
+}
+
+impl Drop for D2hStreamer {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            let _ = self.join();
+        }
+    }
+}
+
+struct Worker {
+    dma: AxiDma,
+    pool: DmaBufferPool,
+    transfer_len: usize,
+    stop: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+}
+
+impl Worker {
+    fn run<F: FnMut(D2hChunk)>(mut self, mut callback: F) -> Result<AxiDma, Error> {
+        let res = self.stream(&mut callback);
+        // Abort the transfer in flight, if any.
+        self.dma.reset();
+        res.map(|()| self.dma)
+    }
+
+    fn stream<F: FnMut(D2hChunk)>(&mut self, callback: &mut F) -> Result<(), Error> {
+        let mut current = match self.acquire() {
+            Some(buffer) => buffer,
+            None => return Ok(()),
+        };
+        self.start(&current)?;
+
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let completed = self.dma.wait_d2h_timeout(STOP_POLL)?;
+            self.dma.check_errors_d2h()?;
+            if !completed {
+                continue;
+            }
+            let len = self.dma.size_d2h();
+            self.counters.transfers.fetch_add(1, Ordering::Relaxed);
+            self.counters.bytes.fetch_add(len as u64, Ordering::Relaxed);
+
+            let next = self.pool.try_acquire();
+            match &next {
+                Some(next) => self.start(next)?,
+                None => {
+                    self.counters.overruns.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            // Deliver the buffer before waiting for a free one, which might
+            // be this one once the consumer is done with it.
+            current.sync_for_cpu()?;
+            callback(D2hChunk {
+                buffer: current,
+                len,
+            });
+            current = match next {
+                Some(buffer) => buffer,
+                None => match self.acquire() {
+                    Some(buffer) => {
+                        self.start(&buffer)?;
+                        buffer
+                    }
+                    None => return Ok(()),
+                },
+            };
+        }
+    }
+
+    /// Wait for a free buffer. Returns `None` if the streamer is stopped.
+    fn acquire(&self) -> Option<PooledBuffer> {
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(buffer) = self.pool.acquire_timeout(STOP_POLL) {
+                return Some(buffer);
+            }
+        }
+    }
+
+    fn start(&mut self, buffer: &PooledBuffer) -> Result<(), Error> {
+        self.dma
+            .start_d2h_at(buffer.phys_addr(), buffer.len(), self.transfer_len)
+    }
+}
+
+/// Buffer received by a [`D2hStreamer`]. It goes back to the pool on drop.
+#[derive(Debug)]
+pub struct D2hChunk {
+    buffer: PooledBuffer,
+    len: usize,
+}
+
+impl D2hChunk {
+    pub fn buffer(&self) -> &PooledBuffer {
+        &self.buffer
+    }
+
+    /// Number of bytes received.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Take the buffer out of the chunk, e.g., to reuse it for a transfer.
+    pub fn into_buffer(self) -> PooledBuffer {
+        self.buffer
+    }
+}
+
+impl Deref for D2hChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer.as_slice::<u8>()[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axi_dma::fake::{wait_until, FakeDma, ResetEmulator};
+    use crate::axi_dma::{S2MM_DMASR, S2MM_LENGTH};
+
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn overrun_delivers_completed_buffer() {
+        let mut f = FakeDma::new("d2h_streamer_overrun");
+        let _emulator = ResetEmulator::new(&f.regs);
+        let pool = f.pool(2);
+        let (streamer, rx) = D2hStreamer::start(f.dma(), pool.clone(), 64);
+
+        f.raise_irq();
+        let first = rx.recv_timeout(RECV_TIMEOUT).unwrap();
+        assert_eq!(first.len(), 64);
+        // The consumer holds on to the first buffer, so the second one cannot
+        // be followed by another transfer. It is delivered nonetheless.
+        f.raise_irq();
+        let second = rx.recv_timeout(RECV_TIMEOUT).unwrap();
+        assert_eq!(streamer.stats().overruns, 1);
+        assert_eq!(pool.available(), 0);
+
+        // Once a buffer is back, streaming continues.
+        drop(first);
+        f.raise_irq();
+        let third = rx.recv_timeout(RECV_TIMEOUT).unwrap();
+        assert_eq!(third.buffer().index(), 0);
+        drop(second);
+        drop(third);
+        let stats = streamer.stats();
+        assert_eq!((stats.transfers, stats.bytes), (3, 192));
+        streamer.stop().unwrap();
+    }
+
+    #[test]
+    fn stops_on_error() {
+        let mut f = FakeDma::new("d2h_streamer_error");
+        let _emulator = ResetEmulator::new(&f.regs);
+        let (streamer, rx) = D2hStreamer::start(f.dma(), f.pool(2), 64);
+        // Starting the transfer clears DMASR.
+        wait_until(|| f.regs.get(S2MM_LENGTH) == 64);
+        f.regs.set(S2MM_DMASR, 0x21);
+        f.raise_irq();
+        assert!(rx.recv_timeout(RECV_TIMEOUT).is_err());
+        assert!(matches!(streamer.stop(), Err(Error::DmaSlave(0x21))));
+    }
+
+    #[test]
+    fn transfer_len_exceeds_buffer() {
+        let mut f = FakeDma::new("d2h_streamer_too_long");
+        let _emulator = ResetEmulator::new(&f.regs);
+        let (streamer, _rx) = D2hStreamer::start(f.dma(), f.pool(2), 4097);
+        wait_until(|| streamer.is_finished());
+        assert!(matches!(
+            streamer.stop(),
+            Err(Error::OutOfBounds {
+                len: 4097,
+                size: 4096,
+                ..
+            })
+        ));
+    }
+}
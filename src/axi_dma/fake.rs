@@ -0,0 +1,266 @@
+//! Fake DMA for tests.
+//!
+//! The registers are a regular file and the UIO device is a socket. The peer
+//! of the socket observes the interrupt enables and raises interrupts.
+
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::prelude::*;
+use std::os::fd::OwnedFd;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+use super::AxiDma;
+#[cfg(feature = "async")]
+use super::AxiDmaAsync;
+use super::AxiDmaBase;
+use super::CompletionMode;
+use super::{MM2S_DMACR, S2MM_DMACR};
+use crate::DmaBuffer;
+use crate::DmaBufferOptions;
+use crate::DmaBufferPool;
+use crate::SysRoot;
+
+const REGS_SIZE: usize = 0x1000;
+
+pub(super) struct FakeDma {
+    dir: PathBuf,
+    pub(super) regs: Regs,
+    pub(super) peer: UnixStream,
+    irq: Option<UnixStream>,
+}
+
+impl FakeDma {
+    pub(super) fn new(name: &str) -> FakeDma {
+        let dir =
+            std::env::temp_dir().join(format!("xilinx-dma-fake-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        let map = dir.join("sys/class/uio/uio0/maps/map0");
+        fs::create_dir_all(&map).unwrap();
+        fs::create_dir_all(dir.join("sys/class/u-dma-buf")).unwrap();
+        fs::create_dir_all(dir.join("dev")).unwrap();
+        fs::write(map.join("size"), format!("{:#x}", REGS_SIZE)).unwrap();
+        fs::write(dir.join("dev/uio0"), vec![0u8; REGS_SIZE]).unwrap();
+
+        let regs = Regs::new(&FakeDma::regs_file(&dir));
+        let (irq, peer) = UnixStream::pair().unwrap();
+        peer.set_nonblocking(true).unwrap();
+        FakeDma {
+            dir,
+            regs,
+            peer,
+            irq: Some(irq),
+        }
+    }
+
+    fn regs_file(dir: &std::path::Path) -> File {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dir.join("dev/uio0"))
+            .unwrap()
+    }
+
+    fn root(&self) -> SysRoot {
+        SysRoot::new(self.dir.join("sys"), self.dir.join("dev"))
+    }
+
+    fn base(&self) -> AxiDmaBase {
+        let regs = FakeDma::regs_file(&self.dir);
+        AxiDmaBase::new("uio0", &self.root(), regs.as_raw_fd()).unwrap()
+    }
+
+    /// The DMA, using interrupts. Either this or [`FakeDma::dma_async`] can
+    /// only be taken once.
+    pub(super) fn dma(&mut self) -> AxiDma {
+        AxiDma {
+            dev_fd: File::from(OwnedFd::from(self.irq.take().unwrap())),
+            dma: self.base(),
+            mode: CompletionMode::Interrupt,
+        }
+    }
+
+    /// The DMA with the async-io reactor. Can only be taken once.
+    #[cfg(feature = "async")]
+    pub(super) fn dma_async(&mut self) -> AxiDmaAsync {
+        let irq = File::from(OwnedFd::from(self.irq.take().unwrap()));
+        AxiDmaAsync::from_fake(self.base(), irq)
+    }
+
+    /// A u-dma-buf of `size` bytes backed by a regular file.
+    pub(super) fn buffer(&self, name: &str, size: usize) -> DmaBuffer {
+        let sysfs = self.dir.join("sys/class/u-dma-buf").join(name);
+        fs::create_dir_all(&sysfs).unwrap();
+        for (attr, value) in [
+            ("phys_addr", "0x10000000".to_string()),
+            ("size", size.to_string()),
+            ("debug_vma", "0".to_string()),
+            ("sync_mode", "1".to_string()),
+            ("sync_offset", "0".to_string()),
+            ("sync_size", "0".to_string()),
+            ("sync_direction", "0".to_string()),
+            ("sync_owner", "0".to_string()),
+            ("sync_for_cpu", "0".to_string()),
+            ("sync_for_device", "0".to_string()),
+        ] {
+            fs::write(sysfs.join(attr), value).unwrap();
+        }
+        fs::write(self.dir.join("dev").join(name), vec![0u8; size]).unwrap();
+        DmaBufferOptions::new()
+            .root(self.root())
+            .open(name)
+            .unwrap()
+    }
+
+    /// Pool of `count` buffers of 4 KiB.
+    pub(super) fn pool(&self, count: usize) -> DmaBufferPool {
+        let buffers = (0..count)
+            .map(|i| self.buffer(&format!("udmabuf{}", i), 4096))
+            .collect();
+        DmaBufferPool::from_buffers(buffers)
+    }
+
+    /// Interrupt enables written by the driver since the last call.
+    #[cfg(feature = "async")]
+    pub(super) fn enables(&self) -> usize {
+        let mut peer = &self.peer;
+        let mut buf = [0u8; 64];
+        let mut count = 0;
+        while let Ok(n) = peer.read(&mut buf) {
+            count += n / 4;
+        }
+        count
+    }
+
+    pub(super) fn raise_irq(&self) {
+        let mut peer = &self.peer;
+        peer.write_all(&[1, 0, 0, 0]).unwrap();
+    }
+}
+
+impl Drop for FakeDma {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A mapping of the registers that outlives the DMA.
+pub(super) struct Regs {
+    base: *mut u32,
+}
+
+impl Regs {
+    fn new(file: &File) -> Regs {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                REGS_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+        Regs {
+            base: base as *mut u32,
+        }
+    }
+
+    pub(super) fn get(&self, offset: isize) -> u32 {
+        unsafe { ptr::read_volatile(self.base.offset(offset)) }
+    }
+
+    pub(super) fn set(&self, offset: isize, value: u32) {
+        unsafe { ptr::write_volatile(self.base.offset(offset), value) }
+    }
+}
+
+impl Drop for Regs {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, REGS_SIZE);
+        }
+    }
+}
+
+/// Clears the reset bits of DMACR, like the core does once it is reset.
+///
+/// Has to be dropped before the [`Regs`] it was created for.
+pub(super) struct ResetEmulator {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ResetEmulator {
+    pub(super) fn new(regs: &Regs) -> ResetEmulator {
+        let stop = Arc::new(AtomicBool::new(false));
+        let base = regs.base as usize;
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    for offset in [MM2S_DMACR, S2MM_DMACR] {
+                        let reg = (base as *mut u32).wrapping_offset(offset);
+                        let reg = unsafe { &*(reg as *const AtomicU32) };
+                        reg.fetch_and(!0x4, Ordering::SeqCst);
+                    }
+                    thread::yield_now();
+                }
+            })
+        };
+        ResetEmulator {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for ResetEmulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.take().unwrap().join();
+    }
+}
+
+/// Wait for a worker thread to get to the point where `condition` holds.
+pub(super) fn wait_until(condition: impl Fn() -> bool) {
+    for _ in 0..5000 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("timed out");
+}
+
+#[cfg(feature = "async")]
+struct NoopWaker;
+
+#[cfg(feature = "async")]
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+#[cfg(feature = "async")]
+pub(super) fn noop_waker() -> Waker {
+    Waker::from(Arc::new(NoopWaker))
+}
+
+#[cfg(feature = "async")]
+pub(super) fn poll_once<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(&noop_waker()))
+}
@@ -0,0 +1,297 @@
+use futures_sink::Sink;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use super::slots::Slots;
+#[cfg(feature = "scatter-gather")]
+use crate::scatter_gather::buffer_length;
+use crate::AxiDmaAsync;
+use crate::DmaBuffer;
+use crate::Error;
+#[cfg(feature = "scatter-gather")]
+use crate::SgRing;
+use crate::SyncDirection;
+
+/// Sink that transmits buffers on the H2D channel.
+///
+/// The sink owns a set of buffers. Empty buffers are handed out as
+/// [`H2dSlot`]s with [`H2dSink::slot`], which waits while all buffers are in
+/// flight. Filled slots are sent into the sink and queued on the channel, one
+/// at a time in register mode or through a descriptor ring in scatter gather
+/// mode. Flushing the sink waits until the hardware completed all transfers.
+pub struct H2dSink {
+    dma: AxiDmaAsync,
+    slots: Arc<Slots>,
+    queued: VecDeque<(usize, usize)>,
+    in_flight: VecDeque<usize>,
+    #[cfg(feature = "scatter-gather")]
+    ring: Option<SgRing>,
+    #[cfg(feature = "scatter-gather")]
+    head: usize,
+}
+
+impl fmt::Debug for H2dSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "H2dSink")?;
+        writeln!(f, "  buffers: {}", self.slots.len())?;
+        writeln!(f, "  queued: {:?}", &self.queued)?;
+        write!(f, "  in_flight: {:?}", &self.in_flight)
+    }
+}
+
+impl H2dSink {
+    /// Create a sink that uses register mode transfers.
+    pub fn new(dma: AxiDmaAsync, buffers: Vec<DmaBuffer>) -> H2dSink {
+        assert!(!buffers.is_empty());
+        H2dSink {
+            dma,
+            slots: Arc::new(Slots::new(buffers)),
+            queued: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            #[cfg(feature = "scatter-gather")]
+            ring: None,
+            #[cfg(feature = "scatter-gather")]
+            head: 0,
+        }
+    }
+
+    /// Create a sink that uses scatter gather transfers. Descriptors are
+    /// pointed to the buffers when they are queued, so the ring may be
+    /// shorter than the number of buffers.
+    #[cfg(feature = "scatter-gather")]
+    pub fn with_ring(dma: AxiDmaAsync, ring: SgRing, buffers: Vec<DmaBuffer>) -> H2dSink {
+        let mut sink = H2dSink::new(dma, buffers);
+        sink.ring = Some(ring);
+        sink
+    }
+
+    /// Get an empty slot, waiting until a buffer is available.
+    pub async fn slot(&mut self) -> Result<H2dSlot, Error> {
+        poll_fn(|cx| self.poll_slot(cx)).await
+    }
+
+    pub fn poll_slot(&mut self, cx: &mut Context<'_>) -> Poll<Result<H2dSlot, Error>> {
+        // Get woken when an unused slot is dropped.
+        self.slots.register(cx);
+        self.poll_progress(cx)?;
+        match self.slots.take() {
+            Some(index) => Poll::Ready(Ok(H2dSlot {
+                len: self.slots.buffer(index).size(),
+                slots: Some(self.slots.clone()),
+                index,
+            })),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Queue as many slots on the channel as possible.
+    fn submit(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "scatter-gather")]
+        if let Some(ring) = self.ring.as_mut() {
+            while let Some(&(index, len)) = self.queued.front() {
+                if ring.is_submitted(self.head) {
+                    break;
+                }
+                let descriptor = ring.descriptor_mut(self.head);
+                descriptor.set_buffer_address(self.slots.buffer(index).phys_addr());
+                descriptor.set_buffer_length(buffer_length(len)?);
+                descriptor.set_sof(true);
+                descriptor.set_eof(true);
+                self.dma.enqueue_sg_ring_h2d(ring, self.head)?;
+                self.queued.pop_front();
+                self.in_flight.push_back(index);
+                self.head = (self.head + 1) % ring.len();
+            }
+            return Ok(());
+        }
+
+        if self.in_flight.is_empty() {
+            if let Some((index, len)) = self.queued.pop_front() {
+                self.dma.start_h2d_now(self.slots.buffer(index), len)?;
+                self.in_flight.push_back(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Give completed slots back. Resolves once at least one slot completed.
+    fn poll_reap(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        #[cfg(feature = "scatter-gather")]
+        if let Some(ring) = self.ring.as_mut() {
+            loop {
+                let mut reaped = false;
+                for completed in self.dma.completed_h2d(ring)? {
+                    completed?;
+                    self.slots.release(self.in_flight.pop_front().unwrap());
+                    reaped = true;
+                }
+                if reaped {
+                    return Poll::Ready(Ok(()));
+                }
+                ready!(self.dma.poll_irq_pending(cx))?;
+            }
+        }
+
+        ready!(self.dma.poll_irq(cx))?;
+        self.dma.check_errors_h2d()?;
+        self.slots.release(self.in_flight.pop_front().unwrap());
+        Poll::Ready(Ok(()))
+    }
+
+    /// Drive the channel without blocking, registering for the next
+    /// completion while transfers are in flight.
+    fn poll_progress(&mut self, cx: &mut Context<'_>) -> Result<(), Error> {
+        loop {
+            self.submit()?;
+            if self.in_flight.is_empty() {
+                return Ok(());
+            }
+            match self.poll_reap(cx) {
+                Poll::Ready(res) => res?,
+                Poll::Pending => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Sink<H2dSlot> for H2dSink {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        // Slots are only handed out for buffers of this sink, so there is
+        // always room to queue them.
+        Poll::Ready(self.get_mut().poll_progress(cx))
+    }
+
+    fn start_send(self: Pin<&mut Self>, mut slot: H2dSlot) -> Result<(), Error> {
+        let this = self.get_mut();
+        if !Arc::ptr_eq(slot.slots.as_ref().unwrap(), &this.slots) {
+            // Dropping the slot gives it back to the sink it belongs to.
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "H2D slot belongs to another sink",
+            )
+            .into());
+        }
+        // On error, dropping the slot gives it back.
+        slot.buffer()
+            .sync_for_device_range(0, slot.len, SyncDirection::ToDevice)?;
+        slot.slots = None;
+        this.queued.push_back((slot.index, slot.len));
+        this.submit()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        this.poll_progress(cx)?;
+        if this.queued.is_empty() && this.in_flight.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// An empty buffer of an [`H2dSink`].
+///
+/// Dereferences to the bytes of the buffer. The slot is transmitted by sending
+/// it into the sink. By default, the whole buffer is transmitted; use
+/// [`H2dSlot::set_len`] to send less. The sink syncs the bytes for the device
+/// when the slot is sent. A slot that is dropped without being sent is given
+/// back to the sink.
+pub struct H2dSlot {
+    slots: Option<Arc<Slots>>,
+    index: usize,
+    len: usize,
+}
+
+impl H2dSlot {
+    /// The underlying DMA buffer.
+    pub fn buffer(&self) -> &DmaBuffer {
+        self.slots.as_ref().unwrap().buffer(self.index)
+    }
+
+    /// Number of bytes that will be transmitted.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.buffer().size());
+        self.len = len;
+    }
+}
+
+impl fmt::Debug for H2dSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "H2dSlot ({})", self.buffer().name())?;
+        write!(f, "  len: {:#x?}", &self.len)
+    }
+}
+
+impl Deref for H2dSlot {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer().as_slice::<u8>()
+    }
+}
+
+impl DerefMut for H2dSlot {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // The slot owns the buffer until it is sent or dropped.
+        unsafe { self.buffer().slice_unchecked::<u8>() }
+    }
+}
+
+impl Drop for H2dSlot {
+    fn drop(&mut self) {
+        if let Some(slots) = self.slots.take() {
+            slots.release(self.index);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+    use crate::axi_dma::fake::{noop_waker, FakeDma};
+    use crate::axi_dma::MM2S_LENGTH;
+
+    #[test]
+    fn sent_slot_synced_for_device() {
+        let mut f = FakeDma::new("sink_sync");
+        let dma = f.dma_async();
+        let mut sink = H2dSink::new(dma, vec![f.buffer("udmabuf0", 256)]);
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut slot = match sink.poll_slot(&mut cx) {
+            Poll::Ready(Ok(slot)) => slot,
+            _ => panic!("no slot available"),
+        };
+        slot[..4].copy_from_slice(&[1, 2, 3, 4]);
+        slot.set_len(32);
+        Pin::new(&mut sink).start_send(slot).unwrap();
+
+        let buffer = sink.slots.buffer(0);
+        assert_eq!(buffer.sync_offset().unwrap(), 0);
+        assert_eq!(buffer.sync_size().unwrap(), 32);
+        assert_eq!(buffer.sync_direction().unwrap(), SyncDirection::ToDevice);
+        assert_eq!(f.regs.get(MM2S_LENGTH), 32);
+    }
+}
@@ -0,0 +1,559 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::collections::VecDeque;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::future::poll_fn;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::future::Future;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::task::{Context, Poll, Waker};
+
+use super::AxiDma;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use super::AxiDmaAsync;
+use crate::DmaMemory;
+use crate::Error;
+use crate::PooledBuffer;
+
+/// Statistics of an [`H2dStreamer`] or [`AsyncH2dStreamer`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct H2dStats {
+    pub transfers: u64,
+    pub bytes: u64,
+    pub underruns: u64,
+    /// Time since the first transfer was started.
+    pub elapsed: Duration,
+}
+
+impl H2dStats {
+    /// Average throughput in bytes per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    transfers: AtomicU64,
+    bytes: AtomicU64,
+    underruns: AtomicU64,
+    started: Mutex<Option<Instant>>,
+}
+
+impl Counters {
+    fn started(&self) {
+        self.started
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    fn completed(&self, bytes: usize) {
+        self.transfers.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> H2dStats {
+        H2dStats {
+            transfers: self.transfers.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            elapsed: self
+                .started
+                .lock()
+                .unwrap()
+                .map_or(Duration::ZERO, |s| s.elapsed()),
+        }
+    }
+}
+
+/// Buffer queued for transmission together with the number of bytes to send.
+type Job = (PooledBuffer, usize);
+
+fn stopped() -> Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "H2D streamer stopped").into()
+}
+
+fn check_len(buffer: &PooledBuffer, len: usize) -> Result<(), Error> {
+    if len > buffer.len() {
+        return Err(Error::OutOfBounds {
+            offset: 0,
+            len,
+            size: buffer.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Continuous register mode transmission of buffers from a producer queue.
+///
+/// A worker thread starts MM2S with the next queued buffer right after the
+/// previous transfer completed and returns sent buffers to their pool. If
+/// the queue is empty when a transfer completes and the producer submits more
+/// buffers later, the DMA ran dry, which is counted as an underrun. Neither
+/// the wait for the first buffer nor the end of the stream count.
+pub struct H2dStreamer {
+    queue: Option<mpsc::Sender<Job>>,
+    counters: Arc<Counters>,
+    thread: Option<thread::JoinHandle<Result<AxiDma, Error>>>,
+}
+
+impl fmt::Debug for H2dStreamer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "H2dStreamer")?;
+        write!(f, "  stats: {:?}", self.stats())
+    }
+}
+
+impl H2dStreamer {
+    pub fn start(dma: AxiDma) -> H2dStreamer {
+        let (tx, rx) = mpsc::channel();
+        let counters = Arc::new(Counters::default());
+        let c = counters.clone();
+        let thread = thread::spawn(move || run(dma, rx, c));
+        H2dStreamer {
+            queue: Some(tx),
+            counters,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue the first `len` bytes of `buffer` for transmission.
+    pub fn submit(&self, buffer: PooledBuffer, len: usize) -> Result<(), Error> {
+        check_len(&buffer, len)?;
+        let queue = self.queue.as_ref().ok_or_else(stopped)?;
+        queue.send((buffer, len)).map_err(|_| stopped())
+    }
+
+    pub fn stats(&self) -> H2dStats {
+        self.counters.stats()
+    }
+
+    /// Send all queued buffers, stop the worker, and give back the DMA.
+    /// Returns the error that stopped the worker, if any, or an I/O error if
+    /// the worker panicked.
+    pub fn stop(mut self) -> Result<AxiDma, Error> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<AxiDma, Error> {
+        // Closing the queue lets the worker exit once it is drained.
+        self.queue.take();
+        let thread = self.thread.take().expect("worker already joined");
+        thread
+            .join()
+            .map_err(|_| io::Error::other("H2D streamer thread panicked"))?
+    }
+}
+
+impl Drop for H2dStreamer {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            let _ = self.join();
+        }
+    }
+}
+
+fn run(
+    mut dma: AxiDma,
+    queue: mpsc::Receiver<Job>,
+    counters: Arc<Counters>,
+) -> Result<AxiDma, Error> {
+    let mut job = match queue.recv() {
+        Ok(job) => job,
+        Err(_) => return Ok(dma),
+    };
+    loop {
+        let (buffer, len) = job;
+        counters.started();
+        let res = buffer
+            .sync_for_device()
+            .and_then(|()| dma.start_h2d(&buffer, len))
+            .and_then(|t| t.wait())
+            .and_then(|()| dma.check_errors_h2d());
+        if let Err(e) = res {
+            dma.reset();
+            return Err(e);
+        }
+        counters.completed(len);
+        drop(buffer);
+
+        job = match queue.try_recv() {
+            Ok(next) => next,
+            Err(mpsc::TryRecvError::Empty) => match queue.recv() {
+                Ok(next) => {
+                    counters.underrun();
+                    next
+                }
+                Err(_) => return Ok(dma),
+            },
+            Err(mpsc::TryRecvError::Disconnected) => return Ok(dma),
+        };
+    }
+}
+
+/// Async version of [`H2dStreamer`].
+///
+/// The transmission is driven by the future returned from
+/// [`AsyncH2dStreamer::new`], which has to be spawned on the executor.
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub struct AsyncH2dStreamer {
+    queue: Arc<Queue>,
+    counters: Arc<Counters>,
+    finished: Arc<Finished>,
+}
+
+/// Result of the worker future, handed to [`AsyncH2dStreamer::stop`].
+#[cfg(any(feature = "async", feature = "tokio"))]
+#[derive(Default)]
+struct Finished {
+    state: Mutex<FinishedState>,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+#[derive(Default)]
+struct FinishedState {
+    result: Option<Result<AxiDmaAsync, Error>>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl Finished {
+    /// `None` if the worker future was dropped before it completed.
+    fn set(&self, result: Option<Result<AxiDmaAsync, Error>>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = result;
+        state.done = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_take(&self, cx: &mut Context<'_>) -> Poll<Result<AxiDmaAsync, Error>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.done {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(
+            state
+                .result
+                .take()
+                .unwrap_or_else(|| Err(io::Error::other("H2D streamer worker was dropped").into())),
+        )
+    }
+}
+
+/// Hands the result of the worker to [`Finished`], also if the worker future
+/// is dropped.
+#[cfg(any(feature = "async", feature = "tokio"))]
+struct FinishGuard {
+    finished: Arc<Finished>,
+    result: Option<Result<AxiDmaAsync, Error>>,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl FinishGuard {
+    fn finish(&mut self, result: Result<AxiDmaAsync, Error>) {
+        self.result = Some(result);
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        self.finished.set(self.result.take());
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+#[derive(Default)]
+struct Queue {
+    jobs: Mutex<QueueState>,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+#[derive(Default)]
+struct QueueState {
+    jobs: VecDeque<Job>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl Queue {
+    fn push(&self, job: Job) -> Result<(), Error> {
+        let mut state = self.jobs.lock().unwrap();
+        if state.closed {
+            return Err(stopped());
+        }
+        state.jobs.push_back(job);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn close(&self) {
+        let mut state = self.jobs.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Next job, or `None` if the queue is closed and drained.
+    fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<Job>> {
+        let mut state = self.jobs.lock().unwrap();
+        if let Some(job) = state.jobs.pop_front() {
+            return Poll::Ready(Some(job));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn is_empty(&self) -> bool {
+        self.jobs.lock().unwrap().jobs.is_empty()
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl fmt::Debug for AsyncH2dStreamer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "AsyncH2dStreamer")?;
+        write!(f, "  stats: {:?}", self.stats())
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl AsyncH2dStreamer {
+    /// Create the streamer and the future that drives it. The future
+    /// completes once the streamer is stopped and all queued buffers are
+    /// sent, or on the first error. Its result is returned by
+    /// [`AsyncH2dStreamer::stop`].
+    pub fn new(dma: AxiDmaAsync) -> (AsyncH2dStreamer, impl Future<Output = ()>) {
+        let queue = Arc::new(Queue::default());
+        let counters = Arc::new(Counters::default());
+        let finished = Arc::new(Finished::default());
+        let worker = {
+            let mut guard = FinishGuard {
+                finished: finished.clone(),
+                result: None,
+            };
+            let run = run_async(dma, queue.clone(), counters.clone());
+            async move {
+                guard.finish(run.await);
+            }
+        };
+        let streamer = AsyncH2dStreamer {
+            queue,
+            counters,
+            finished,
+        };
+        (streamer, worker)
+    }
+
+    /// Queue the first `len` bytes of `buffer` for transmission.
+    pub fn submit(&self, buffer: PooledBuffer, len: usize) -> Result<(), Error> {
+        check_len(&buffer, len)?;
+        self.queue.push((buffer, len))
+    }
+
+    pub fn stats(&self) -> H2dStats {
+        self.counters.stats()
+    }
+
+    /// Send all queued buffers, wait for the worker future to complete, and
+    /// give back the DMA. Returns the error that stopped the worker, if any,
+    /// or an I/O error if the worker future was dropped.
+    pub async fn stop(self) -> Result<AxiDmaAsync, Error> {
+        self.queue.close();
+        poll_fn(|cx| self.finished.poll_take(cx)).await
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl Drop for AsyncH2dStreamer {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+async fn run_async(
+    mut dma: AxiDmaAsync,
+    queue: Arc<Queue>,
+    counters: Arc<Counters>,
+) -> Result<AxiDmaAsync, Error> {
+    let mut starved = false;
+    while let Some((buffer, len)) = poll_fn(|cx| queue.poll_pop(cx)).await {
+        // The DMA ran dry, unless the queue was closed meanwhile.
+        if starved {
+            counters.underrun();
+        }
+        counters.started();
+        // The transfer resets the DMA if the future is dropped while it is in
+        // flight.
+        let res = match buffer.sync_for_device() {
+            Ok(()) => match dma.start_h2d(&buffer, len).await {
+                Ok(transfer) => transfer.wait().await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        let res = res.and_then(|()| dma.check_errors_h2d());
+        if let Err(e) = res {
+            dma.reset();
+            return Err(e);
+        }
+        counters.completed(len);
+        drop(buffer);
+
+        starved = queue.is_empty();
+    }
+    Ok(dma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axi_dma::fake::{wait_until, FakeDma, ResetEmulator};
+    use crate::axi_dma::{MM2S_DMASR, MM2S_LENGTH};
+
+    #[test]
+    fn no_underrun_at_start_or_end() {
+        let mut f = FakeDma::new("h2d_streamer_drain");
+        let pool = f.pool(2);
+        let streamer = H2dStreamer::start(f.dma());
+        // Waiting for the first buffer is no underrun.
+        thread::sleep(Duration::from_millis(20));
+        streamer.submit(pool.try_acquire().unwrap(), 16).unwrap();
+        streamer.submit(pool.try_acquire().unwrap(), 24).unwrap();
+        f.raise_irq();
+        f.raise_irq();
+        // Neither is the empty queue after the last buffer.
+        wait_until(|| streamer.stats().transfers == 2);
+        thread::sleep(Duration::from_millis(20));
+        let stats = streamer.stats();
+        streamer.stop().unwrap();
+        assert_eq!((stats.bytes, stats.underruns), (40, 0));
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn underrun_when_queue_runs_dry() {
+        let mut f = FakeDma::new("h2d_streamer_underrun");
+        let pool = f.pool(2);
+        let streamer = H2dStreamer::start(f.dma());
+        streamer.submit(pool.try_acquire().unwrap(), 32).unwrap();
+        wait_until(|| f.regs.get(MM2S_LENGTH) == 32);
+        f.raise_irq();
+        wait_until(|| streamer.stats().transfers == 1);
+        assert_eq!(streamer.stats().underruns, 0);
+
+        streamer.submit(pool.try_acquire().unwrap(), 48).unwrap();
+        wait_until(|| f.regs.get(MM2S_LENGTH) == 48);
+        assert_eq!(streamer.stats().underruns, 1);
+        f.raise_irq();
+        wait_until(|| streamer.stats().transfers == 2);
+        let stats = streamer.stats();
+        streamer.stop().unwrap();
+        assert_eq!((stats.bytes, stats.underruns), (80, 1));
+    }
+
+    #[test]
+    fn stops_on_error() {
+        let mut f = FakeDma::new("h2d_streamer_error");
+        let _emulator = ResetEmulator::new(&f.regs);
+        let pool = f.pool(1);
+        let streamer = H2dStreamer::start(f.dma());
+        streamer.submit(pool.try_acquire().unwrap(), 32).unwrap();
+        // Starting the transfer clears DMASR.
+        wait_until(|| f.regs.get(MM2S_LENGTH) == 32);
+        f.regs.set(MM2S_DMASR, 0x21);
+        f.raise_irq();
+        assert!(matches!(streamer.stop(), Err(Error::DmaSlave(0x21))));
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[cfg(feature = "async")]
+    mod async_streamer {
+        use std::pin::pin;
+
+        use super::*;
+        use crate::axi_dma::fake::poll_once;
+
+        #[test]
+        fn underrun_only_while_producing() {
+            let mut f = FakeDma::new("async_h2d_streamer_underrun");
+            let pool = f.pool(3);
+            let (streamer, worker) = AsyncH2dStreamer::new(f.dma_async());
+            let mut worker = pin!(worker);
+            assert!(poll_once(worker.as_mut()).is_pending());
+
+            streamer.submit(pool.try_acquire().unwrap(), 32).unwrap();
+            assert!(poll_once(worker.as_mut()).is_pending());
+            assert_eq!(f.regs.get(MM2S_LENGTH), 32);
+            f.raise_irq();
+            assert!(poll_once(worker.as_mut()).is_pending());
+            assert_eq!(streamer.stats().transfers, 1);
+            assert_eq!(streamer.stats().underruns, 0);
+
+            // The queue ran dry while the producer was still active.
+            streamer.submit(pool.try_acquire().unwrap(), 16).unwrap();
+            streamer.submit(pool.try_acquire().unwrap(), 24).unwrap();
+            f.raise_irq();
+            f.raise_irq();
+            assert!(poll_once(worker.as_mut()).is_pending());
+            let stats = streamer.stats();
+            assert_eq!((stats.transfers, stats.bytes), (3, 72));
+            assert_eq!(stats.underruns, 1);
+
+            // The empty queue at the end of the stream is no underrun.
+            let mut stop = pin!(streamer.stop());
+            assert!(poll_once(stop.as_mut()).is_pending());
+            assert!(poll_once(worker.as_mut()).is_ready());
+            assert!(matches!(poll_once(stop.as_mut()), Poll::Ready(Ok(_))));
+            assert_eq!(pool.available(), 3);
+        }
+
+        #[test]
+        fn stops_on_error() {
+            let mut f = FakeDma::new("async_h2d_streamer_error");
+            let _emulator = ResetEmulator::new(&f.regs);
+            let pool = f.pool(1);
+            let (streamer, worker) = AsyncH2dStreamer::new(f.dma_async());
+            let mut worker = pin!(worker);
+            streamer.submit(pool.try_acquire().unwrap(), 32).unwrap();
+            assert!(poll_once(worker.as_mut()).is_pending());
+            f.regs.set(MM2S_DMASR, 0x21);
+            f.raise_irq();
+            assert!(poll_once(worker.as_mut()).is_ready());
+            assert!(matches!(
+                poll_once(pin!(streamer.stop())),
+                Poll::Ready(Err(Error::DmaSlave(0x21)))
+            ));
+        }
+    }
+}
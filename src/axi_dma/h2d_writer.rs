@@ -0,0 +1,336 @@
+use std::fmt;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::future::poll_fn;
+use std::io;
+use std::io::Write;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::pin::Pin;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use std::task::{ready, Context, Poll};
+
+use super::AxiDma;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use super::AxiDmaAsync;
+#[cfg(any(feature = "async", feature = "tokio"))]
+use super::Channel;
+use crate::DmaBuffer;
+
+/// [`Write`] adapter that sends data through MM2S.
+///
+/// Data is collected in a DMA buffer and sent once the buffer is full or on
+/// [`flush`](Write::flush). Each transfer is one packet, i.e., `flush` marks
+/// a packet boundary. Dropping the writer flushes it, ignoring errors.
+pub struct H2dWriter {
+    // Only `None` once taken by `into_inner`.
+    dma: Option<AxiDma>,
+    buffer: Option<DmaBuffer>,
+    filled: usize,
+}
+
+impl fmt::Debug for H2dWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "H2dWriter ({})", self.buffer().name())?;
+        write!(f, "  filled: {:#x?}", &self.filled)
+    }
+}
+
+impl H2dWriter {
+    pub fn new(dma: AxiDma, buffer: DmaBuffer) -> H2dWriter {
+        H2dWriter {
+            dma: Some(dma),
+            buffer: Some(buffer),
+            filled: 0,
+        }
+    }
+
+    /// Flush the buffered data and give back the DMA and the buffer.
+    pub fn into_inner(mut self) -> io::Result<(AxiDma, DmaBuffer)> {
+        self.flush()?;
+        Ok((self.dma.take().unwrap(), self.buffer.take().unwrap()))
+    }
+
+    fn buffer(&self) -> &DmaBuffer {
+        self.buffer.as_ref().unwrap()
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let (dma, buffer) = (self.dma.as_mut().unwrap(), self.buffer.as_ref().unwrap());
+        buffer.sync_for_device()?;
+        dma.start_h2d(buffer, self.filled)?.wait()?;
+        self.filled = 0;
+        Ok(())
+    }
+}
+
+impl Write for H2dWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.buffer().size();
+        if self.filled == size {
+            self.send()?;
+        }
+        let n = buf.len().min(size - self.filled);
+        let buffer = self.buffer.as_mut().unwrap();
+        buffer.as_mut_slice::<u8>()[self.filled..self.filled + n].copy_from_slice(&buf[..n]);
+        self.filled += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.filled > 0 {
+            self.send()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for H2dWriter {
+    fn drop(&mut self) {
+        if self.dma.is_some() {
+            let _ = self.flush();
+        }
+    }
+}
+
+/// Async version of [`H2dWriter`], implementing
+/// [`AsyncWrite`](futures_io::AsyncWrite).
+///
+/// Dropping the writer cannot wait for a transfer, so data that was not
+/// flushed is discarded and a transfer in flight is aborted by resetting the
+/// DMA. Use [`AsyncH2dWriter::into_inner`] or close the writer to send it.
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub struct AsyncH2dWriter {
+    // Only `None` once taken by `into_inner`.
+    dma: Option<AxiDmaAsync>,
+    buffer: Option<DmaBuffer>,
+    filled: usize,
+    in_flight: bool,
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl fmt::Debug for AsyncH2dWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "AsyncH2dWriter ({})", self.buffer().name())?;
+        writeln!(f, "  filled: {:#x?}", &self.filled)?;
+        write!(f, "  in_flight: {:?}", &self.in_flight)
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl AsyncH2dWriter {
+    pub fn new(dma: AxiDmaAsync, buffer: DmaBuffer) -> AsyncH2dWriter {
+        AsyncH2dWriter {
+            dma: Some(dma),
+            buffer: Some(buffer),
+            filled: 0,
+            in_flight: false,
+        }
+    }
+
+    /// Flush the buffered data and give back the DMA and the buffer.
+    pub async fn into_inner(mut self) -> io::Result<(AxiDmaAsync, DmaBuffer)> {
+        poll_fn(|cx| futures_io::AsyncWrite::poll_flush(Pin::new(&mut self), cx)).await?;
+        Ok((self.dma.take().unwrap(), self.buffer.take().unwrap()))
+    }
+
+    fn buffer(&self) -> &DmaBuffer {
+        self.buffer.as_ref().unwrap()
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        let (dma, buffer) = (self.dma.as_mut().unwrap(), self.buffer.as_ref().unwrap());
+        buffer.sync_for_device()?;
+        dma.start_h2d_now(buffer, self.filled)?;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    /// Wait for the transfer in flight, after which the buffer is empty.
+    fn poll_sent(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.in_flight {
+            ready!(self.dma.as_mut().unwrap().poll_wait_h2d(cx))?;
+            self.in_flight = false;
+            self.filled = 0;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl futures_io::AsyncWrite for AsyncH2dWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let size = this.buffer().size();
+        loop {
+            ready!(this.poll_sent(cx))?;
+            if this.filled < size {
+                break;
+            }
+            this.start()?;
+        }
+        let n = buf.len().min(size - this.filled);
+        let buffer = this.buffer.as_mut().unwrap();
+        buffer.as_mut_slice::<u8>()[this.filled..this.filled + n].copy_from_slice(&buf[..n]);
+        this.filled += n;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.in_flight && this.filled > 0 {
+            this.start()?;
+        }
+        this.poll_sent(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(any(feature = "async", feature = "tokio"))]
+impl Drop for AsyncH2dWriter {
+    fn drop(&mut self) {
+        if self.in_flight {
+            let _ = self.dma.as_mut().unwrap().abort(Channel::H2d);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axi_dma::fake::FakeDma;
+    use crate::axi_dma::MM2S_LENGTH;
+
+    #[test]
+    fn packets_of_buffer_size_and_flush() {
+        let mut f = FakeDma::new("h2d_writer_packets");
+        let buffer = f.buffer("udmabuf0", 64);
+        let mut writer = H2dWriter::new(f.dma(), buffer);
+        let data: Vec<u8> = (0..100).collect();
+
+        // Collected until the buffer is full.
+        assert_eq!(writer.write(&data).unwrap(), 64);
+        assert_eq!(f.regs.get(MM2S_LENGTH), 0);
+        f.raise_irq();
+        assert_eq!(writer.write(&data[64..]).unwrap(), 36);
+        assert_eq!(f.regs.get(MM2S_LENGTH), 64);
+
+        // Flushing sends a short packet.
+        f.raise_irq();
+        writer.flush().unwrap();
+        assert_eq!(f.regs.get(MM2S_LENGTH), 36);
+        let (_dma, buffer) = writer.into_inner().unwrap();
+        assert_eq!(buffer.as_slice::<u8>()[..36], data[64..]);
+    }
+
+    #[test]
+    fn into_inner_flushes() {
+        let mut f = FakeDma::new("h2d_writer_into_inner");
+        let buffer = f.buffer("udmabuf0", 64);
+        let mut writer = H2dWriter::new(f.dma(), buffer);
+        writer.write_all(&[1; 10]).unwrap();
+        f.raise_irq();
+        writer.into_inner().unwrap();
+        assert_eq!(f.regs.get(MM2S_LENGTH), 10);
+    }
+
+    #[cfg(feature = "async")]
+    mod async_writer {
+        use std::pin::pin;
+
+        use futures_io::AsyncWrite;
+
+        use super::*;
+        use crate::axi_dma::fake::{noop_waker, poll_once, ResetEmulator};
+        use crate::axi_dma::MM2S_DMASR;
+
+        fn poll_write(writer: &mut AsyncH2dWriter, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let waker = noop_waker();
+            Pin::new(writer).poll_write(&mut Context::from_waker(&waker), buf)
+        }
+
+        fn poll_flush(writer: &mut AsyncH2dWriter) -> Poll<io::Result<()>> {
+            let waker = noop_waker();
+            Pin::new(writer).poll_flush(&mut Context::from_waker(&waker))
+        }
+
+        #[test]
+        fn packets_of_buffer_size_and_flush() {
+            let mut f = FakeDma::new("async_h2d_writer_packets");
+            let buffer = f.buffer("udmabuf0", 64);
+            let mut writer = AsyncH2dWriter::new(f.dma_async(), buffer);
+            let data: Vec<u8> = (0..100).collect();
+
+            assert!(matches!(
+                poll_write(&mut writer, &data),
+                Poll::Ready(Ok(64))
+            ));
+            assert_eq!(f.regs.get(MM2S_LENGTH), 0);
+            // The full buffer is sent before more data is taken.
+            assert!(poll_write(&mut writer, &data[64..]).is_pending());
+            assert_eq!(f.regs.get(MM2S_LENGTH), 64);
+            f.raise_irq();
+            assert!(matches!(
+                poll_write(&mut writer, &data[64..]),
+                Poll::Ready(Ok(36))
+            ));
+
+            assert!(poll_flush(&mut writer).is_pending());
+            assert_eq!(f.regs.get(MM2S_LENGTH), 36);
+            f.raise_irq();
+            assert!(matches!(poll_flush(&mut writer), Poll::Ready(Ok(()))));
+            // Nothing left to send.
+            assert!(matches!(poll_flush(&mut writer), Poll::Ready(Ok(()))));
+        }
+
+        #[test]
+        fn into_inner_flushes() {
+            let mut f = FakeDma::new("async_h2d_writer_into_inner");
+            let buffer = f.buffer("udmabuf0", 64);
+            let mut writer = AsyncH2dWriter::new(f.dma_async(), buffer);
+            assert!(matches!(
+                poll_write(&mut writer, &[1; 10]),
+                Poll::Ready(Ok(10))
+            ));
+            let mut into_inner = pin!(writer.into_inner());
+            assert!(poll_once(into_inner.as_mut()).is_pending());
+            assert_eq!(f.regs.get(MM2S_LENGTH), 10);
+            f.raise_irq();
+            assert!(matches!(poll_once(into_inner), Poll::Ready(Ok(_))));
+        }
+
+        #[test]
+        fn dropped_with_unflushed_data() {
+            let mut f = FakeDma::new("async_h2d_writer_dropped");
+            let buffer = f.buffer("udmabuf0", 64);
+            let mut writer = AsyncH2dWriter::new(f.dma_async(), buffer);
+            assert!(matches!(
+                poll_write(&mut writer, &[1; 10]),
+                Poll::Ready(Ok(10))
+            ));
+            drop(writer);
+            assert_eq!(f.regs.get(MM2S_LENGTH), 0);
+        }
+
+        #[test]
+        fn dropped_while_sending() {
+            let mut f = FakeDma::new("async_h2d_writer_dropped_sending");
+            let _emulator = ResetEmulator::new(&f.regs);
+            let buffer = f.buffer("udmabuf0", 64);
+            let mut writer = AsyncH2dWriter::new(f.dma_async(), buffer);
+            assert!(matches!(
+                poll_write(&mut writer, &[1; 10]),
+                Poll::Ready(Ok(10))
+            ));
+            assert!(poll_flush(&mut writer).is_pending());
+            f.regs.set(MM2S_DMASR, 0x1);
+            drop(writer);
+            assert_eq!(f.regs.get(MM2S_DMASR), 0x7000);
+        }
+    }
+}
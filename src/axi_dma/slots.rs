@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::task::{Context, Waker};
+
+use crate::DmaBuffer;
+
+/// Buffers shared between a streaming driver and the guards it hands out.
+///
+/// Guards give their buffer back by index, which wakes the driver.
+pub(crate) struct Slots {
+    buffers: Vec<DmaBuffer>,
+    free: Mutex<VecDeque<usize>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Slots {
+    pub(crate) fn new(buffers: Vec<DmaBuffer>) -> Slots {
+        let free = (0..buffers.len()).collect();
+        Slots {
+            buffers,
+            free: Mutex::new(free),
+            waker: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub(crate) fn buffer(&self, index: usize) -> &DmaBuffer {
+        &self.buffers[index]
+    }
+
+    /// Take the slot that was released first.
+    pub(crate) fn take(&self) -> Option<usize> {
+        self.free.lock().unwrap().pop_front()
+    }
+
+    /// Take a specific slot, if it is free.
+    #[cfg(feature = "scatter-gather")]
+    pub(crate) fn take_index(&self, index: usize) -> bool {
+        let mut free = self.free.lock().unwrap();
+        match free.iter().position(|&i| i == index) {
+            Some(pos) => {
+                free.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn release(&self, index: usize) {
+        self.free.lock().unwrap().push_back(index);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+
+    /// Wake the task of `cx` when the next slot is released.
+    pub(crate) fn register(&self, cx: &Context<'_>) {
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+    }
+}
@@ -1,22 +1,230 @@
 use std::fmt;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io;
 use std::io::prelude::*;
 use std::mem;
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::slice;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::dma_memory;
+use crate::lock::lock_exclusive;
+use crate::DmaRegion;
 use crate::Error;
+use crate::Pod;
+use crate::SysRoot;
+
+/// Direction of a cache synchronisation, see [`DmaBuffer::sync_for_cpu_range`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncDirection {
+    Bidirectional,
+    ToDevice,
+    FromDevice,
+}
+
+impl SyncDirection {
+    fn value(self) -> u32 {
+        match self {
+            SyncDirection::Bidirectional => 0,
+            SyncDirection::ToDevice => 1,
+            SyncDirection::FromDevice => 2,
+        }
+    }
+}
+
+/// Cache mode of the mapping, as configured by `sync_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// No cache mode with the always flag, i.e., mappings stay cached even
+    /// with `O_SYNC`.
+    Cached,
+    Uncached,
+    WriteCombine,
+    DmaCoherent,
+    /// `sync_mode` is 0, which the driver treats as not configured.
+    Invalid,
+}
+
+impl SyncMode {
+    fn from_value(value: u32) -> SyncMode {
+        match value & 0b11 {
+            0 if value & 0b100 != 0 => SyncMode::Cached,
+            0 => SyncMode::Invalid,
+            1 => SyncMode::Uncached,
+            2 => SyncMode::WriteCombine,
+            _ => SyncMode::DmaCoherent,
+        }
+    }
+
+    fn value(self) -> Option<u32> {
+        match self {
+            SyncMode::Uncached => Some(1),
+            SyncMode::WriteCombine => Some(2),
+            SyncMode::DmaCoherent => Some(3),
+            SyncMode::Cached | SyncMode::Invalid => None,
+        }
+    }
+}
+
+/// Cache mode requested for a mapping, see [`DmaBuffer::open_with`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MappingMode {
+    Cached,
+    Uncached,
+    WriteCombine,
+}
+
+/// Whether the buffer is currently synced for the CPU or the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncOwner {
+    Cpu,
+    Device,
+}
+
+struct SyncRange {
+    offset: File,
+    size: File,
+    direction: File,
+}
 
 pub struct DmaBuffer {
     name: String,
+    sysfs: PathBuf,
     size: usize,
     phys_addr: usize,
     buffer: *mut libc::c_void,
-    sync_mode: bool,
+    sync_mode: SyncMode,
+    sync_mode_always: bool,
     debug_vma: bool,
+    dma_coherent: Option<bool>,
+    driver_version: Option<String>,
+    device_name: Option<String>,
     sync_for_cpu: File,
     sync_for_device: File,
+    sync_range: Mutex<SyncRange>,
+    // Kept open, since closing it releases the lock.
+    _dev: File,
+    // Last field, so that all files are closed before the buffer is deleted.
+    created: Option<CreatedBuffer>,
+}
+
+/// Options for opening a [`DmaBuffer`].
+#[derive(Clone, Debug)]
+pub struct DmaBufferOptions {
+    mapping: Option<MappingMode>,
+    root: SysRoot,
+    lock: bool,
+}
+
+impl DmaBufferOptions {
+    pub fn new() -> DmaBufferOptions {
+        DmaBufferOptions {
+            mapping: None,
+            root: SysRoot::default(),
+            lock: true,
+        }
+    }
+
+    /// Cache mode of the mapping, see [`DmaBuffer::open_with`]. By default,
+    /// the mode configured in the driver is used.
+    pub fn mapping(mut self, mode: MappingMode) -> DmaBufferOptions {
+        self.mapping = Some(mode);
+        self
+    }
+
+    pub fn root(mut self, root: SysRoot) -> DmaBufferOptions {
+        self.root = root;
+        self
+    }
+
+    /// Take an exclusive advisory lock on the buffer device (default),
+    /// failing with [`Error::Busy`] if it is already open with a lock. This
+    /// includes another [`DmaBuffer`] of the same device in this process.
+    pub fn lock(mut self, lock: bool) -> DmaBufferOptions {
+        self.lock = lock;
+        self
+    }
+
+    pub fn open(&self, name: &str) -> Result<DmaBuffer, Error> {
+        DmaBuffer::open(name, self)
+    }
+}
+
+impl Default for DmaBufferOptions {
+    fn default() -> DmaBufferOptions {
+        DmaBufferOptions::new()
+    }
+}
+
+/// Options for [`DmaBuffer::create`].
+#[derive(Clone, Debug)]
+pub struct CreateOptions {
+    delete_on_drop: bool,
+    timeout: Duration,
+    open: DmaBufferOptions,
+}
+
+impl CreateOptions {
+    pub fn new() -> CreateOptions {
+        CreateOptions {
+            delete_on_drop: true,
+            timeout: Duration::from_secs(1),
+            open: DmaBufferOptions::new(),
+        }
+    }
+
+    /// Delete the buffer again when the `DmaBuffer` is dropped (default).
+    pub fn delete_on_drop(mut self, delete: bool) -> CreateOptions {
+        self.delete_on_drop = delete;
+        self
+    }
+
+    /// How long to wait for the device nodes to show up (default 1s).
+    pub fn timeout(mut self, timeout: Duration) -> CreateOptions {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Options to open the buffer once it has been created.
+    pub fn open_options(mut self, options: DmaBufferOptions) -> CreateOptions {
+        self.open = options;
+        self
+    }
+}
+
+impl Default for CreateOptions {
+    fn default() -> CreateOptions {
+        CreateOptions::new()
+    }
+}
+
+#[derive(Debug)]
+struct CreatedBuffer {
+    name: String,
+    mgr: PathBuf,
+}
+
+impl CreatedBuffer {
+    fn create(name: &str, size: usize, root: &SysRoot) -> Result<CreatedBuffer, Error> {
+        let mgr = root.device("u-dma-buf-mgr");
+        mgr_command(&mgr, &format!("create {} {}", name, size))?;
+        Ok(CreatedBuffer {
+            name: name.to_string(),
+            mgr,
+        })
+    }
+}
+
+impl Drop for CreatedBuffer {
+    fn drop(&mut self) {
+        let _ = mgr_command(&self.mgr, &format!("delete {}", self.name));
+    }
 }
 
 impl fmt::Debug for DmaBuffer {
@@ -26,48 +234,99 @@ impl fmt::Debug for DmaBuffer {
         writeln!(f, "  phys_addr: {:#x?}", &self.phys_addr)?;
         writeln!(f, "  buffer: {:?}", &self.buffer)?;
         writeln!(f, "  sync_mode: {:?}", &self.sync_mode)?;
-        write!(f, "  debug_vma: {:?}", &self.debug_vma)
+        writeln!(f, "  sync_mode_always: {:?}", &self.sync_mode_always)?;
+        writeln!(f, "  debug_vma: {:?}", &self.debug_vma)?;
+        writeln!(f, "  dma_coherent: {:?}", &self.dma_coherent)?;
+        writeln!(f, "  driver_version: {:?}", &self.driver_version)?;
+        writeln!(f, "  device_name: {:?}", &self.device_name)?;
+        write!(f, "  delete_on_drop: {:?}", self.created.is_some())
     }
 }
 
 impl DmaBuffer {
     pub fn new(name: &str) -> Result<DmaBuffer, Error> {
-        let phy_f = format!("/sys/class/u-dma-buf/{}/phys_addr", name);
-        let mut phy_f = File::open(phy_f)?;
-        let mut buff = String::new();
-        phy_f.read_to_string(&mut buff)?;
-        let buff = buff.trim().trim_start_matches("0x");
-        let phys_addr = usize::from_str_radix(buff, 16)?;
-
-        let size_f = format!("/sys/class/u-dma-buf/{}/size", name);
-        let mut size_f = File::open(size_f)?;
-        let mut buff = String::new();
-        size_f.read_to_string(&mut buff)?;
-        let buff = buff.trim();
-        let size = buff.parse::<usize>()?;
-
-        let debug_f = format!("/sys/class/u-dma-buf/{}/debug_vma", name);
-        let mut debug_f = File::open(debug_f)?;
-        let mut buff = String::new();
-        debug_f.read_to_string(&mut buff)?;
-        let debug_vma = buff.trim() != "0";
-
-        let sync_f = format!("/sys/class/u-dma-buf/{}/sync_mode", name);
-        let mut sync_f = File::open(sync_f)?;
-        let mut buff = String::new();
-        sync_f.read_to_string(&mut buff)?;
-        let sync_mode = buff.trim() != "0";
+        DmaBufferOptions::new().open(name)
+    }
+
+    /// Open the buffer with the given cache mode for the mapping.
+    ///
+    /// This sets `sync_mode` and opens the device with `O_SYNC` for uncached
+    /// mappings. `sync_mode` is shared by all users of the buffer, but it only
+    /// takes effect when mapping the buffer. It is only written if it differs.
+    /// The always flag is kept for uncached mappings and cleared for cached
+    /// ones, which it would otherwise make uncached.
+    pub fn open_with(name: &str, mode: MappingMode) -> Result<DmaBuffer, Error> {
+        DmaBufferOptions::new().mapping(mode).open(name)
+    }
+
+    fn open(name: &str, options: &DmaBufferOptions) -> Result<DmaBuffer, Error> {
+        let sysfs = options.root.u_dma_buf(name);
+        let mode = options.mapping;
+
+        let path = options.root.device(name);
+        let mut flags = 0;
+        if matches!(
+            mode,
+            Some(MappingMode::Uncached) | Some(MappingMode::WriteCombine)
+        ) {
+            flags |= libc::O_SYNC;
+        }
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(flags)
+            .open(&path)?;
+        // Lock before touching any attribute, which may be in use.
+        if options.lock {
+            lock_exclusive(&dev, &path)?;
+        }
+
+        if let Some(mode) = mode {
+            let current = read_attr(&sysfs, "sync_mode")?.parse::<u32>()?;
+            let value = match mode {
+                // The always flag applies the cache mode without `O_SYNC`.
+                MappingMode::Cached if current & 0b11 != 0 => current & 0b11,
+                MappingMode::Cached => current,
+                // Keep the always flag, which was configured for all users.
+                MappingMode::Uncached => (current & 0b100) | 1,
+                MappingMode::WriteCombine => (current & 0b100) | 2,
+            };
+            if value != current {
+                store_attr(&sysfs, "sync_mode", value)?;
+            }
+        }
+
+        let phys_addr = read_attr(&sysfs, "phys_addr")?;
+        let phys_addr = usize::from_str_radix(phys_addr.trim_start_matches("0x"), 16)?;
+        let size = read_attr(&sysfs, "size")?.parse::<usize>()?;
+        let debug_vma = read_attr(&sysfs, "debug_vma")? != "0";
+
+        let sync_mode = read_attr(&sysfs, "sync_mode")?.parse::<u32>()?;
+        let sync_mode_always = sync_mode & 0b100 != 0;
+        let sync_mode = SyncMode::from_value(sync_mode);
+
+        let dma_coherent = read_optional_attr(&sysfs, "dma_coherent")?.map(|v| v != "0");
+        let driver_version = read_optional_attr(&sysfs, "driver_version")?;
+        let device_name = std::fs::read_link(sysfs.join("device"))
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()));
 
         let mut sync_open_options = OpenOptions::new();
         sync_open_options.write(true);
-        let sync_for_cpu = format!("/sys/class/u-dma-buf/{}/sync_for_cpu", name);
+        let sync_for_cpu = sysfs.join("sync_for_cpu");
         let sync_for_cpu = sync_open_options.open(sync_for_cpu)?;
 
-        let sync_for_device = format!("/sys/class/u-dma-buf/{}/sync_for_device", name);
+        let sync_for_device = sysfs.join("sync_for_device");
         let sync_for_device = sync_open_options.write(true).open(sync_for_device)?;
 
-        let dev = format!("/dev/{}", name);
-        let dev = OpenOptions::new().read(true).write(true).open(dev)?;
+        let sync_offset = sysfs.join("sync_offset");
+        let sync_size = sysfs.join("sync_size");
+        let sync_direction = sysfs.join("sync_direction");
+        let sync_range = Mutex::new(SyncRange {
+            offset: sync_open_options.open(sync_offset)?,
+            size: sync_open_options.open(sync_size)?,
+            direction: sync_open_options.open(sync_direction)?,
+        });
 
         let buffer;
         unsafe {
@@ -86,19 +345,97 @@ impl DmaBuffer {
 
         Ok(DmaBuffer {
             name: name.to_string(),
+            sysfs,
             size,
             phys_addr,
             buffer,
             sync_mode,
+            sync_mode_always,
             debug_vma,
+            dma_coherent,
+            driver_version,
+            device_name,
             sync_for_cpu,
             sync_for_device,
+            sync_range,
+            _dev: dev,
+            created: None,
         })
     }
 
+    /// Create a new buffer of `size` bytes at runtime through
+    /// `u-dma-buf-mgr` in the device root.
+    ///
+    /// Fails with [`Error::CreateTimeout`] if the device nodes do not show up
+    /// within the timeout of the options.
+    pub fn create(name: &str, size: usize, options: CreateOptions) -> Result<DmaBuffer, Error> {
+        let root = &options.open.root;
+        let created = CreatedBuffer::create(name, size, root)?;
+
+        let sysfs = root.u_dma_buf(name).join("phys_addr");
+        let dev = root.device(name);
+        let start = Instant::now();
+        while !(sysfs.exists() && dev.exists()) {
+            if start.elapsed() > options.timeout {
+                return Err(Error::CreateTimeout(name.to_string()));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut buffer = options.open.open(name)?;
+        if options.delete_on_drop {
+            buffer.created = Some(created);
+        } else {
+            mem::forget(created);
+        }
+        Ok(buffer)
+    }
+
+    #[deprecated(note = "use as_slice, as_mut_slice, or slice_unchecked")]
     #[allow(clippy::mut_from_ref)]
     pub fn slice<T>(&self) -> &mut [T] {
-        unsafe { slice::from_raw_parts_mut(self.buffer as *mut T, self.size / mem::size_of::<T>()) }
+        unsafe { self.slice_unchecked() }
+    }
+
+    /// Mutable view of the buffer from a shared reference.
+    ///
+    /// # Safety
+    /// The caller has to make sure that there are no other references to the
+    /// accessed memory, that `T` is valid for its contents, and that the buffer
+    /// is aligned for `T`.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn slice_unchecked<T>(&self) -> &mut [T] {
+        slice::from_raw_parts_mut(self.buffer as *mut T, self.size / mem::size_of::<T>())
+    }
+
+    /// View the buffer as a slice of `T`.
+    ///
+    /// Panics if the buffer is not aligned for `T`.
+    pub fn as_slice<T: Pod>(&self) -> &[T] {
+        dma_memory::as_slice(self)
+    }
+
+    /// View the buffer as a mutable slice of `T`.
+    ///
+    /// Panics if the buffer is not aligned for `T`.
+    pub fn as_mut_slice<T: Pod>(&mut self) -> &mut [T] {
+        dma_memory::as_mut_slice(self)
+    }
+
+    /// The whole buffer as a region, see [`DmaRegion::allocate`].
+    pub fn region(&mut self) -> DmaRegion<'_> {
+        DmaRegion::new(self)
+    }
+
+    /// Split the buffer into the regions `[0, mid)` and `[mid, size)`.
+    pub fn split_at(&mut self, mid: usize) -> (DmaRegion<'_>, DmaRegion<'_>) {
+        self.region().split_at(mid)
+    }
+
+    /// Split the buffer into regions of `size` bytes. The last region might be
+    /// shorter.
+    pub fn chunks(&mut self, size: usize) -> Vec<DmaRegion<'_>> {
+        self.region().chunks(size)
     }
 
     pub fn name(&self) -> &str {
@@ -117,21 +454,178 @@ impl DmaBuffer {
         self.buffer
     }
 
-    pub fn sync_mode(&self) -> bool {
+    pub fn sync_mode(&self) -> SyncMode {
         self.sync_mode
     }
 
+    /// Whether the sync mode applies regardless of `O_SYNC` when opening the
+    /// device.
+    pub fn sync_mode_always(&self) -> bool {
+        self.sync_mode_always
+    }
+
+    /// Set `sync_mode`. With `always`, the mode also applies to mappings
+    /// opened without `O_SYNC`. Only mappings created afterwards are
+    /// affected.
+    ///
+    /// Cached mappings are selected through [`MappingMode`] when opening, so
+    /// `Cached` and `Invalid` cannot be set.
+    pub fn set_sync_mode(&mut self, mode: SyncMode, always: bool) -> Result<(), Error> {
+        let value = mode.value().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sync_mode cannot be set to {:?}", mode),
+            )
+        })?;
+        let value = if always { value | 0b100 } else { value };
+        store_attr(&self.sysfs, "sync_mode", value)?;
+        self.sync_mode = mode;
+        self.sync_mode_always = always;
+        Ok(())
+    }
+
     pub fn debug_vma(&self) -> bool {
         self.debug_vma
     }
 
-    pub fn sync_for_cpu(&mut self) -> Result<(), Error> {
-        self.sync_for_cpu.write_all(b"1")?;
+    /// Enable or disable the kernel log messages of the driver on mapping
+    /// and unmapping.
+    pub fn set_debug_vma(&mut self, debug_vma: bool) -> Result<(), Error> {
+        store_attr(&self.sysfs, "debug_vma", u32::from(debug_vma))?;
+        self.debug_vma = debug_vma;
         Ok(())
     }
 
-    pub fn sync_for_device(&mut self) -> Result<(), Error> {
-        self.sync_for_device.write_all(b"1")?;
+    /// `None` if the driver does not report it.
+    pub fn dma_coherent(&self) -> Option<bool> {
+        self.dma_coherent
+    }
+
+    /// `None` if the driver does not report it.
+    pub fn driver_version(&self) -> Option<&str> {
+        self.driver_version.as_deref()
+    }
+
+    /// Name of the parent device the buffer was allocated for.
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    pub fn sync_offset(&self) -> Result<usize, Error> {
+        parse_usize(&read_attr(&self.sysfs, "sync_offset")?)
+    }
+
+    /// Every sync programs its own range, so this only affects syncs
+    /// triggered outside of this crate.
+    pub fn set_sync_offset(&self, offset: usize) -> Result<(), Error> {
+        write_attr(&self.sync_range.lock().unwrap().offset, offset)?;
+        Ok(())
+    }
+
+    pub fn sync_size(&self) -> Result<usize, Error> {
+        parse_usize(&read_attr(&self.sysfs, "sync_size")?)
+    }
+
+    /// See [`DmaBuffer::set_sync_offset`].
+    pub fn set_sync_size(&self, size: usize) -> Result<(), Error> {
+        write_attr(&self.sync_range.lock().unwrap().size, size)?;
+        Ok(())
+    }
+
+    pub fn sync_direction(&self) -> Result<SyncDirection, Error> {
+        match read_attr(&self.sysfs, "sync_direction")?.parse::<u32>()? {
+            0 => Ok(SyncDirection::Bidirectional),
+            1 => Ok(SyncDirection::ToDevice),
+            2 => Ok(SyncDirection::FromDevice),
+            v => Err(invalid_attr("sync_direction", v)),
+        }
+    }
+
+    /// See [`DmaBuffer::set_sync_offset`].
+    pub fn set_sync_direction(&self, direction: SyncDirection) -> Result<(), Error> {
+        write_attr(
+            &self.sync_range.lock().unwrap().direction,
+            direction.value(),
+        )?;
+        Ok(())
+    }
+
+    pub fn sync_owner(&self) -> Result<SyncOwner, Error> {
+        match read_attr(&self.sysfs, "sync_owner")?.parse::<u32>()? {
+            0 => Ok(SyncOwner::Cpu),
+            1 => Ok(SyncOwner::Device),
+            v => Err(invalid_attr("sync_owner", v)),
+        }
+    }
+
+    /// Sync the whole buffer for the CPU.
+    pub fn sync_for_cpu(&self) -> Result<(), Error> {
+        self.sync_range(
+            &self.sync_for_cpu,
+            0,
+            self.size,
+            SyncDirection::Bidirectional,
+        )
+    }
+
+    /// Sync the whole buffer for the device.
+    pub fn sync_for_device(&self) -> Result<(), Error> {
+        self.sync_range(
+            &self.sync_for_device,
+            0,
+            self.size,
+            SyncDirection::Bidirectional,
+        )
+    }
+
+    /// Sync `len` bytes starting at `offset` for the CPU, e.g., to invalidate
+    /// only the bytes received by a D2H transfer.
+    ///
+    /// Fails with [`Error::OutOfBounds`] if the range exceeds the buffer.
+    pub fn sync_for_cpu_range(
+        &self,
+        offset: usize,
+        len: usize,
+        direction: SyncDirection,
+    ) -> Result<(), Error> {
+        self.sync_range(&self.sync_for_cpu, offset, len, direction)
+    }
+
+    /// Sync `len` bytes starting at `offset` for the device, e.g., to flush
+    /// only the bytes of an H2D transfer.
+    ///
+    /// Fails with [`Error::OutOfBounds`] if the range exceeds the buffer.
+    pub fn sync_for_device_range(
+        &self,
+        offset: usize,
+        len: usize,
+        direction: SyncDirection,
+    ) -> Result<(), Error> {
+        self.sync_range(&self.sync_for_device, offset, len, direction)
+    }
+
+    fn sync_range(
+        &self,
+        trigger: &File,
+        offset: usize,
+        len: usize,
+        direction: SyncDirection,
+    ) -> Result<(), Error> {
+        if offset > self.size || len > self.size - offset {
+            return Err(Error::OutOfBounds {
+                offset,
+                len,
+                size: self.size,
+            });
+        }
+
+        // The range is a driver-wide setting, so it has to stay untouched
+        // until the sync was triggered. Every sync programs its own range.
+        let range = self.sync_range.lock().unwrap();
+        write_attr(&range.offset, offset)?;
+        write_attr(&range.size, len)?;
+        write_attr(&range.direction, direction.value())?;
+        write_attr(trigger, 1)?;
         Ok(())
     }
 }
@@ -144,5 +638,52 @@ impl Drop for DmaBuffer {
     }
 }
 
+fn mgr_command(mgr: &Path, cmd: &str) -> io::Result<()> {
+    let mut mgr = OpenOptions::new().write(true).open(mgr)?;
+    mgr.write_all(cmd.as_bytes())
+}
+
+fn read_attr(sysfs: &Path, attr: &str) -> io::Result<String> {
+    let mut buff = String::new();
+    File::open(sysfs.join(attr))?.read_to_string(&mut buff)?;
+    Ok(buff.trim().to_string())
+}
+
+/// Write an attribute through a file that is kept open. Sysfs ignores the
+/// file position, so the value is written at the start.
+fn write_attr(file: &File, value: impl fmt::Display) -> io::Result<()> {
+    file.write_all_at(value.to_string().as_bytes(), 0)
+}
+
+fn store_attr(sysfs: &Path, attr: &str, value: impl fmt::Display) -> io::Result<()> {
+    write_attr(
+        &OpenOptions::new().write(true).open(sysfs.join(attr))?,
+        value,
+    )
+}
+
+fn read_optional_attr(sysfs: &Path, attr: &str) -> io::Result<Option<String>> {
+    match read_attr(sysfs, attr) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_usize(value: &str) -> Result<usize, Error> {
+    match value.strip_prefix("0x") {
+        Some(hex) => Ok(usize::from_str_radix(hex, 16)?),
+        None => Ok(value.parse::<usize>()?),
+    }
+}
+
+fn invalid_attr(attr: &str, value: u32) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected {} value {}", attr, value),
+    )
+    .into()
+}
+
 unsafe impl Send for DmaBuffer {}
 unsafe impl Sync for DmaBuffer {}
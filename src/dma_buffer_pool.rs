@@ -0,0 +1,437 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::dma_memory;
+use crate::DmaBuffer;
+use crate::DmaMemory;
+use crate::Error;
+use crate::Pod;
+use crate::SyncDirection;
+
+/// Fixed set of DMA buffers that are checked out as [`PooledBuffer`]s and go
+/// back to the pool when the guard is dropped.
+///
+/// The pool can be cloned cheaply and shared across threads.
+#[derive(Clone)]
+pub struct DmaBufferPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    buffers: Vec<DmaBuffer>,
+    entries: Vec<Entry>,
+    free: Mutex<VecDeque<usize>>,
+    available: Condvar,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    buffer: usize,
+    offset: usize,
+    len: usize,
+}
+
+impl fmt::Debug for DmaBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "DmaBufferPool")?;
+        writeln!(f, "  buffers: {:?}", self.inner.buffers.len())?;
+        writeln!(f, "  entries: {:?}", self.inner.entries.len())?;
+        write!(f, "  available: {:?}", self.available())
+    }
+}
+
+impl DmaBufferPool {
+    /// Pool of the u-dma-bufs with the given names.
+    pub fn new(names: &[&str]) -> Result<DmaBufferPool, Error> {
+        let buffers = names
+            .iter()
+            .map(|name| DmaBuffer::new(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DmaBufferPool::from_buffers(buffers))
+    }
+
+    pub fn from_buffers(buffers: Vec<DmaBuffer>) -> DmaBufferPool {
+        let entries = buffers
+            .iter()
+            .enumerate()
+            .map(|(i, b)| Entry {
+                buffer: i,
+                offset: 0,
+                len: b.size(),
+            })
+            .collect();
+        DmaBufferPool::with_entries(buffers, entries)
+    }
+
+    /// Pool of chunks of `chunk_size` bytes of one big buffer. Remaining bytes
+    /// at the end of the buffer are not used.
+    pub fn from_chunks(buffer: DmaBuffer, chunk_size: usize) -> DmaBufferPool {
+        assert!(chunk_size > 0);
+        let entries = (0..buffer.size() / chunk_size)
+            .map(|i| Entry {
+                buffer: 0,
+                offset: i * chunk_size,
+                len: chunk_size,
+            })
+            .collect();
+        DmaBufferPool::with_entries(vec![buffer], entries)
+    }
+
+    fn with_entries(buffers: Vec<DmaBuffer>, entries: Vec<Entry>) -> DmaBufferPool {
+        let free = (0..entries.len()).collect();
+        DmaBufferPool {
+            inner: Arc::new(PoolInner {
+                buffers,
+                entries,
+                free: Mutex::new(free),
+                available: Condvar::new(),
+                wakers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Number of buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.inner.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.entries.is_empty()
+    }
+
+    /// Number of buffers that are currently not checked out.
+    pub fn available(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+
+    pub fn try_acquire(&self) -> Option<PooledBuffer> {
+        let index = self.inner.free.lock().unwrap().pop_front()?;
+        Some(self.guard(index))
+    }
+
+    /// Block until a buffer is available.
+    pub fn acquire_blocking(&self) -> PooledBuffer {
+        let mut free = self.inner.free.lock().unwrap();
+        loop {
+            if let Some(index) = free.pop_front() {
+                return self.guard(index);
+            }
+            free = self.inner.available.wait(free).unwrap();
+        }
+    }
+
+    /// Block until a buffer is available, but at most for `timeout`.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<PooledBuffer> {
+        let free = self.inner.free.lock().unwrap();
+        let (mut free, _) = self
+            .inner
+            .available
+            .wait_timeout_while(free, timeout, |free| free.is_empty())
+            .unwrap();
+        let index = free.pop_front()?;
+        Some(self.guard(index))
+    }
+
+    /// Wait until a buffer is available.
+    pub async fn acquire(&self) -> PooledBuffer {
+        poll_fn(|cx| self.poll_acquire(cx)).await
+    }
+
+    pub fn poll_acquire(&self, cx: &mut Context<'_>) -> Poll<PooledBuffer> {
+        if let Some(buffer) = self.try_acquire() {
+            return Poll::Ready(buffer);
+        }
+        {
+            let mut wakers = self.inner.wakers.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // A buffer might have been released before the waker was registered.
+        match self.try_acquire() {
+            Some(buffer) => Poll::Ready(buffer),
+            None => Poll::Pending,
+        }
+    }
+
+    fn guard(&self, index: usize) -> PooledBuffer {
+        PooledBuffer {
+            pool: self.inner.clone(),
+            index,
+        }
+    }
+}
+
+impl PoolInner {
+    fn release(&self, index: usize) {
+        self.free.lock().unwrap().push_back(index);
+        self.available.notify_one();
+        // Wake all waiting tasks, since a woken task might have been dropped
+        // in the meantime.
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Buffer checked out from a [`DmaBufferPool`].
+///
+/// It can be used directly for transfers and goes back to the pool on drop.
+pub struct PooledBuffer {
+    pool: Arc<PoolInner>,
+    index: usize,
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry = self.entry();
+        writeln!(f, "PooledBuffer ({})", self.parent().name())?;
+        writeln!(f, "  offset: {:#x?}", &entry.offset)?;
+        write!(f, "  len: {:#x?}", &entry.len)
+    }
+}
+
+impl PooledBuffer {
+    fn entry(&self) -> Entry {
+        self.pool.entries[self.index]
+    }
+
+    fn parent(&self) -> &DmaBuffer {
+        &self.pool.buffers[self.entry().buffer]
+    }
+
+    /// Index of the buffer in the pool.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// View the buffer as a slice of `T`.
+    ///
+    /// Panics if the buffer is not aligned for `T`.
+    pub fn as_slice<T: Pod>(&self) -> &[T] {
+        dma_memory::as_slice(self)
+    }
+
+    /// View the buffer as a mutable slice of `T`.
+    ///
+    /// Panics if the buffer is not aligned for `T`.
+    pub fn as_mut_slice<T: Pod>(&mut self) -> &mut [T] {
+        dma_memory::as_mut_slice(self)
+    }
+}
+
+unsafe impl DmaMemory for PooledBuffer {
+    fn phys_addr(&self) -> usize {
+        self.parent().phys_addr() + self.entry().offset
+    }
+
+    fn len(&self) -> usize {
+        self.entry().len
+    }
+
+    fn as_ptr(&self) -> *mut libc::c_void {
+        unsafe { (self.parent().buffer() as *mut u8).add(self.entry().offset) as *mut libc::c_void }
+    }
+
+    fn sync_for_cpu(&self) -> Result<(), Error> {
+        let entry = self.entry();
+        if entry.len == self.parent().size() {
+            self.parent().sync_for_cpu()
+        } else {
+            self.parent()
+                .sync_for_cpu_range(entry.offset, entry.len, SyncDirection::Bidirectional)
+        }
+    }
+
+    fn sync_for_device(&self) -> Result<(), Error> {
+        let entry = self.entry();
+        if entry.len == self.parent().size() {
+            self.parent().sync_for_device()
+        } else {
+            self.parent().sync_for_device_range(
+                entry.offset,
+                entry.len,
+                SyncDirection::Bidirectional,
+            )
+        }
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.release(self.index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+    use std::thread;
+    use std::time::Instant;
+
+    use crate::DmaBufferOptions;
+    use crate::SysRoot;
+
+    const PHYS_ADDR: usize = 0x1000_0000;
+
+    /// u-dma-bufs backed by regular files.
+    struct FakeRoot {
+        dir: PathBuf,
+    }
+
+    impl FakeRoot {
+        fn new(name: &str) -> FakeRoot {
+            let dir = std::env::temp_dir().join(format!(
+                "xilinx-dma-pool-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("sys/class/u-dma-buf")).unwrap();
+            fs::create_dir_all(dir.join("dev")).unwrap();
+            FakeRoot { dir }
+        }
+
+        fn buffer(&self, name: &str, size: usize) -> DmaBuffer {
+            let sysfs = self.dir.join("sys/class/u-dma-buf").join(name);
+            fs::create_dir_all(&sysfs).unwrap();
+            for (attr, value) in [
+                ("phys_addr", format!("{:#x}", PHYS_ADDR)),
+                ("size", size.to_string()),
+                ("debug_vma", "0".to_string()),
+                ("sync_mode", "1".to_string()),
+                ("sync_offset", "0".to_string()),
+                ("sync_size", "0".to_string()),
+                ("sync_direction", "0".to_string()),
+                ("sync_owner", "0".to_string()),
+                ("sync_for_cpu", "0".to_string()),
+                ("sync_for_device", "0".to_string()),
+            ] {
+                fs::write(sysfs.join(attr), value).unwrap();
+            }
+            fs::write(self.dir.join("dev").join(name), vec![0u8; size]).unwrap();
+            DmaBufferOptions::new()
+                .root(SysRoot::new(self.dir.join("sys"), self.dir.join("dev")))
+                .open(name)
+                .unwrap()
+        }
+
+        fn pool(&self, count: usize) -> DmaBufferPool {
+            let buffers = (0..count)
+                .map(|i| self.buffer(&format!("udmabuf{}", i), 4096))
+                .collect();
+            DmaBufferPool::from_buffers(buffers)
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[derive(Default)]
+    struct FlagWaker(AtomicBool);
+
+    impl Wake for FlagWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn returned_on_drop() {
+        let fake = FakeRoot::new("returned_on_drop");
+        let pool = fake.pool(2);
+        let first = pool.try_acquire().unwrap();
+        let second = pool.try_acquire().unwrap();
+        assert_eq!((first.index(), second.index()), (0, 1));
+        assert_eq!(pool.available(), 0);
+        assert!(pool.try_acquire().is_none());
+
+        drop(first);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.try_acquire().unwrap().index(), 0);
+        drop(second);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn acquire_timeout() {
+        let fake = FakeRoot::new("acquire_timeout");
+        let pool = fake.pool(1);
+        let buffer = pool.acquire_timeout(Duration::from_millis(10)).unwrap();
+
+        let start = Instant::now();
+        assert!(pool.acquire_timeout(Duration::from_millis(20)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(buffer);
+        });
+        assert_eq!(
+            pool.acquire_timeout(Duration::from_secs(10))
+                .unwrap()
+                .index(),
+            0
+        );
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn waiter_woken_by_release() {
+        let fake = FakeRoot::new("waiter_woken");
+        let pool = fake.pool(1);
+        let buffer = pool.try_acquire().unwrap();
+
+        let flag = Arc::new(FlagWaker::default());
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(pool.poll_acquire(&mut cx).is_pending());
+        assert!(!flag.0.load(Ordering::SeqCst));
+
+        drop(buffer);
+        assert!(flag.0.load(Ordering::SeqCst));
+        match pool.poll_acquire(&mut cx) {
+            Poll::Ready(buffer) => assert_eq!(buffer.index(), 0),
+            Poll::Pending => panic!("buffer not available after the release"),
+        }
+    }
+
+    #[test]
+    fn chunk_bounds() {
+        let fake = FakeRoot::new("chunk_bounds");
+        let buffer = fake.buffer("udmabuf0", 4096);
+        let base = buffer.buffer() as usize;
+        // The 96 bytes after the last chunk are not used.
+        let pool = DmaBufferPool::from_chunks(buffer, 1000);
+        assert_eq!(pool.len(), 4);
+
+        let chunks: Vec<PooledBuffer> = (0..4).map(|_| pool.try_acquire().unwrap()).collect();
+        assert!(pool.try_acquire().is_none());
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.index(), i);
+            assert_eq!(chunk.phys_addr(), PHYS_ADDR + i * 1000);
+            assert_eq!(chunk.as_ptr() as usize, base + i * 1000);
+            assert_eq!(DmaMemory::len(chunk), 1000);
+            assert_eq!(chunk.as_slice::<u8>().len(), 1000);
+        }
+
+        // Syncs cover the chunk only.
+        chunks[3].sync_for_device().unwrap();
+        let parent = chunks[3].parent();
+        assert_eq!(parent.sync_offset().unwrap(), 3000);
+        assert_eq!(parent.sync_size().unwrap(), 1000);
+    }
+}
@@ -0,0 +1,132 @@
+use std::mem;
+use std::slice;
+
+use crate::DmaBuffer;
+use crate::DmaRegion;
+use crate::Error;
+use crate::Pod;
+use crate::SyncDirection;
+
+/// Memory that can be used as source or destination of DMA transfers.
+///
+/// # Safety
+/// The DMA is programmed and slices are created from the values returned by
+/// the implementation alone. For as long as the value lives:
+///
+/// - `as_ptr` points to `len` bytes that are valid for reads and writes, and
+///   that are neither freed nor moved.
+/// - `phys_addr` is the address of the same bytes as seen by the DMA.
+/// - Both keep returning the same values.
+pub unsafe trait DmaMemory {
+    /// Address of the memory as seen by the DMA.
+    fn phys_addr(&self) -> usize;
+
+    /// Length in bytes.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Virtual address of the memory.
+    fn as_ptr(&self) -> *mut libc::c_void;
+
+    /// Make data written by the DMA visible to the CPU.
+    fn sync_for_cpu(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Make data written by the CPU visible to the DMA.
+    fn sync_for_device(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+unsafe impl DmaMemory for DmaBuffer {
+    fn phys_addr(&self) -> usize {
+        DmaBuffer::phys_addr(self)
+    }
+
+    fn len(&self) -> usize {
+        self.size()
+    }
+
+    fn as_ptr(&self) -> *mut libc::c_void {
+        self.buffer()
+    }
+
+    fn sync_for_cpu(&self) -> Result<(), Error> {
+        DmaBuffer::sync_for_cpu(self)
+    }
+
+    fn sync_for_device(&self) -> Result<(), Error> {
+        DmaBuffer::sync_for_device(self)
+    }
+}
+
+unsafe impl DmaMemory for DmaRegion<'_> {
+    fn phys_addr(&self) -> usize {
+        DmaRegion::phys_addr(self)
+    }
+
+    fn len(&self) -> usize {
+        DmaRegion::len(self)
+    }
+
+    fn as_ptr(&self) -> *mut libc::c_void {
+        self.buffer()
+    }
+
+    fn sync_for_cpu(&self) -> Result<(), Error> {
+        self.parent().sync_for_cpu_range(
+            self.offset(),
+            DmaRegion::len(self),
+            SyncDirection::Bidirectional,
+        )
+    }
+
+    fn sync_for_device(&self) -> Result<(), Error> {
+        self.parent().sync_for_device_range(
+            self.offset(),
+            DmaRegion::len(self),
+            SyncDirection::Bidirectional,
+        )
+    }
+}
+
+/// View `memory` as a slice of `T`.
+///
+/// Panics if the memory is not aligned for `T`.
+pub(crate) fn as_slice<T: Pod, M: DmaMemory + ?Sized>(memory: &M) -> &[T] {
+    assert_aligned::<T, M>(memory);
+    // SAFETY: the memory is valid for `len` bytes, see `DmaMemory`.
+    unsafe {
+        slice::from_raw_parts(
+            memory.as_ptr() as *const T,
+            memory.len() / mem::size_of::<T>(),
+        )
+    }
+}
+
+/// View `memory` as a mutable slice of `T`.
+///
+/// Panics if the memory is not aligned for `T`.
+pub(crate) fn as_mut_slice<T: Pod, M: DmaMemory + ?Sized>(memory: &mut M) -> &mut [T] {
+    assert_aligned::<T, M>(memory);
+    // SAFETY: the memory is valid for `len` bytes, see `DmaMemory`, and it
+    // is borrowed mutably.
+    unsafe {
+        slice::from_raw_parts_mut(
+            memory.as_ptr() as *mut T,
+            memory.len() / mem::size_of::<T>(),
+        )
+    }
+}
+
+fn assert_aligned<T, M: DmaMemory + ?Sized>(memory: &M) {
+    assert_eq!(
+        memory.as_ptr() as usize % mem::align_of::<T>(),
+        0,
+        "DMA memory is not aligned for the requested type"
+    );
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use crate::dma_memory;
+use crate::DmaBuffer;
+use crate::Pod;
+
+/// Part of a [`DmaBuffer`] that can be used independently of the other parts.
+///
+/// Regions are created from a mutable borrow of the buffer, so they never
+/// overlap.
+pub struct DmaRegion<'a> {
+    parent: &'a DmaBuffer,
+    offset: usize,
+    len: usize,
+}
+
+impl fmt::Debug for DmaRegion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "DmaRegion ({})", self.name())?;
+        writeln!(f, "  offset: {:#x?}", &self.offset)?;
+        writeln!(f, "  len: {:#x?}", &self.len)?;
+        write!(f, "  phys_addr: {:#x?}", &self.phys_addr())
+    }
+}
+
+impl<'a> DmaRegion<'a> {
+    pub(crate) fn new(parent: &'a mut DmaBuffer) -> DmaRegion<'a> {
+        let len = parent.size();
+        DmaRegion {
+            parent,
+            offset: 0,
+            len,
+        }
+    }
+
+    /// Name of the parent buffer.
+    pub fn name(&self) -> &str {
+        self.parent.name()
+    }
+
+    /// Offset of the region in the parent buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn phys_addr(&self) -> usize {
+        self.parent.phys_addr() + self.offset
+    }
+
+    pub fn buffer(&self) -> *mut libc::c_void {
+        unsafe { (self.parent.buffer() as *mut u8).add(self.offset) as *mut libc::c_void }
+    }
+
+    /// Split the region into `[0, mid)` and `[mid, len)`.
+    pub fn split_at(self, mid: usize) -> (DmaRegion<'a>, DmaRegion<'a>) {
+        assert!(mid <= self.len);
+        (
+            DmaRegion {
+                parent: self.parent,
+                offset: self.offset,
+                len: mid,
+            },
+            DmaRegion {
+                parent: self.parent,
+                offset: self.offset + mid,
+                len: self.len - mid,
+            },
+        )
+    }
+
+    /// Split the region into chunks of `size` bytes. The last chunk might be
+    /// shorter.
+    pub fn chunks(mut self, size: usize) -> Vec<DmaRegion<'a>> {
+        assert!(size > 0);
+        let mut chunks = Vec::new();
+        while self.len > size {
+            let (chunk, rest) = self.split_at(size);
+            chunks.push(chunk);
+            self = rest;
+        }
+        if !self.is_empty() {
+            chunks.push(self);
+        }
+        chunks
+    }
+
+    /// Take `size` bytes from the front of the region, with the physical
+    /// address aligned to `align`. Returns `None` if the region is too small.
+    pub fn allocate(&mut self, size: usize, align: usize) -> Option<DmaRegion<'a>> {
+        assert!(align.is_power_of_two());
+        let pad = self.phys_addr().wrapping_neg() & (align - 1);
+        if pad.checked_add(size)? > self.len {
+            return None;
+        }
+        let region = DmaRegion {
+            parent: self.parent,
+            offset: self.offset + pad,
+            len: size,
+        };
+        self.offset += pad + size;
+        self.len -= pad + size;
+        Some(region)
+    }
+
+    /// View the region as a slice of `T`.
+    ///
+    /// Panics if the region is not aligned for `T`.
+    pub fn as_slice<T: Pod>(&self) -> &[T] {
+        dma_memory::as_slice(self)
+    }
+
+    /// View the region as a mutable slice of `T`.
+    ///
+    /// Panics if the region is not aligned for `T`.
+    pub fn as_mut_slice<T: Pod>(&mut self) -> &mut [T] {
+        dma_memory::as_mut_slice(self)
+    }
+
+    pub(crate) fn parent(&self) -> &DmaBuffer {
+        self.parent
+    }
+}
@@ -2,16 +2,47 @@ mod axi_dma;
 
 mod dma_buffer;
 pub use axi_dma::AxiDma;
+pub use axi_dma::AxiDmaOptions;
+pub use axi_dma::CompletionMode;
+pub use axi_dma::Transfer;
+pub use axi_dma::{D2hChunk, D2hStats, D2hStreamer};
+pub use axi_dma::{D2hReader, H2dWriter};
+pub use axi_dma::{H2dStats, H2dStreamer};
 
-#[cfg(feature = "async")]
-pub use axi_dma::AxiDmaAsync;
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use axi_dma::{AsyncD2hReader, AsyncH2dWriter};
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use axi_dma::{AsyncH2dStreamer, AsyncTransfer, AxiDmaAsync};
+#[cfg(any(feature = "async", feature = "tokio"))]
+pub use axi_dma::{D2hBuffer, D2hStream, H2dSink, H2dSlot};
 
 pub use dma_buffer::DmaBuffer;
+pub use dma_buffer::{
+    CreateOptions, DmaBufferOptions, MappingMode, SyncDirection, SyncMode, SyncOwner,
+};
+mod sys_root;
+pub use sys_root::SysRoot;
+mod dma_buffer_pool;
+pub use dma_buffer_pool::{DmaBufferPool, PooledBuffer};
+mod dma_memory;
+pub use dma_memory::DmaMemory;
+mod dma_region;
+pub use dma_region::DmaRegion;
+
+mod lock;
+
+mod uio_device;
+pub use uio_device::UioDevice;
+
+mod pod;
+pub use pod::Pod;
 
 #[cfg(feature = "scatter-gather")]
 mod scatter_gather;
 #[cfg(feature = "scatter-gather")]
-pub use scatter_gather::{SgDescriptor, SG_DESCRIPTOR_LEN};
+pub use axi_dma::SgCompletions;
+#[cfg(feature = "scatter-gather")]
+pub use scatter_gather::{SgDescriptor, SgRing, SG_DESCRIPTOR_LEN};
 
 #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
 mod dmb;
@@ -43,8 +74,44 @@ pub enum Error {
     SgSlave(u32),
     #[error("Scatter Gather decode error (DMASR 0x{0:08x})")]
     SgDecode(u32),
+    #[error("Scatter Gather fault at descriptor 0x{phys_addr:x} (STATUS 0x{status:08x}, DMASR 0x{dmasr:08x})")]
+    SgFault {
+        phys_addr: usize,
+        status: u32,
+        dmasr: u32,
+    },
+    #[error("Range of {len} bytes at offset {offset} exceeds the size {size}.")]
+    OutOfBounds {
+        offset: usize,
+        len: usize,
+        size: usize,
+    },
+    #[error("Length of {len} bytes exceeds the maximum of {max} bytes.")]
+    TooLong { len: usize, max: usize },
+    #[error("Descriptor {0} of the ring has not been reaped yet.")]
+    DescriptorBusy(usize),
+    #[error("{} is already open elsewhere{}.", path.display(), pid.map(|p| format!(" (PID {p})")).unwrap_or_default())]
+    Busy {
+        path: std::path::PathBuf,
+        pid: Option<u32>,
+    },
+    #[error("No UIO device found for {0}.")]
+    NotFound(String),
+    #[error("Timed out waiting for DMA completion.")]
+    Timeout,
+    #[error("Timed out waiting for u-dma-buf {0} to be created.")]
+    CreateTimeout(String),
     #[error("I/O Error")]
     Io(#[from] std::io::Error),
     #[error("Failed to parse integer from sysfs files.")]
     Parse(#[from] std::num::ParseIntError),
 }
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            e => std::io::Error::other(e),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::Error;
+
+/// Take an exclusive advisory lock on an opened device node.
+///
+/// The lock is held as long as the file stays open. If another open file
+/// description holds it, also one of this process, [`Error::Busy`] is returned
+/// with the PID of its owner, if it can be found in `/proc/locks`.
+pub(crate) fn lock_exclusive(file: &File, path: &Path) -> Result<(), Error> {
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+        return Err(err.into());
+    }
+    let pid = file
+        .metadata()
+        .ok()
+        .and_then(|m| lock_holder(m.dev(), m.ino()));
+    Err(Error::Busy {
+        path: path.to_path_buf(),
+        pid,
+    })
+}
+
+/// Find the PID holding a `flock` on the given inode.
+///
+/// Lines of `/proc/locks` look like
+/// `1: FLOCK  ADVISORY  WRITE 1234 00:05:567 0 EOF`, with major and minor in
+/// hex. Blocked requests are prefixed with `->` and skipped.
+fn lock_holder(dev: u64, ino: u64) -> Option<u32> {
+    let locks = std::fs::read_to_string("/proc/locks").ok()?;
+    parse_lock_holder(&locks, dev, ino)
+}
+
+fn parse_lock_holder(locks: &str, dev: u64, ino: u64) -> Option<u32> {
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0xfff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0xff);
+    locks.lines().find_map(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        if fields.next()? != "FLOCK" {
+            return None;
+        }
+        let pid = fields.nth(2)?.parse::<u32>().ok()?;
+        let mut id = fields.next()?.split(':');
+        let lock_major = u64::from_str_radix(id.next()?, 16).ok()?;
+        let lock_minor = u64::from_str_radix(id.next()?, 16).ok()?;
+        let lock_ino = id.next()?.parse::<u64>().ok()?;
+        (lock_major == major && lock_minor == minor && lock_ino == ino).then_some(pid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    // 08:01, encoded like `st_dev`.
+    const DEV: u64 = 0x801;
+
+    #[test]
+    fn parse_flock() {
+        let locks = "\
+1: POSIX  ADVISORY  WRITE 100 08:01:42 0 EOF
+2: FLOCK  ADVISORY  WRITE 200 08:01:41 0 EOF
+2: -> FLOCK  ADVISORY  WRITE 300 08:01:42 0 EOF
+3: FLOCK  ADVISORY  WRITE 400 08:01:42 0 EOF
+";
+        assert_eq!(parse_lock_holder(locks, DEV, 42), Some(400));
+        assert_eq!(parse_lock_holder(locks, DEV, 41), Some(200));
+        assert_eq!(parse_lock_holder(locks, DEV, 43), None);
+        assert_eq!(parse_lock_holder(locks, 0x802, 42), None);
+    }
+
+    #[test]
+    fn parse_large_dev() {
+        // Major 1003 and minor 12345 spill into the upper bits of `st_dev`.
+        let dev = (0x1000 << 32) | (0x003 << 8) | (0x12300 << 12) | 0x45;
+        let locks = "1: FLOCK  ADVISORY  WRITE 500 1003:12345:7 0 EOF\n";
+        assert_eq!(parse_lock_holder(locks, dev, 7), Some(500));
+    }
+
+    #[test]
+    fn busy_within_process() {
+        let path = std::env::temp_dir().join(format!("xilinx-dma-lock-{}", std::process::id()));
+        let first = File::create(&path).unwrap();
+        let second = File::open(&path).unwrap();
+        lock_exclusive(&first, &path).unwrap();
+        let err = lock_exclusive(&second, &path).unwrap_err();
+        let _ = fs::remove_file(&path);
+        match err {
+            Error::Busy { path: busy, pid } => {
+                assert_eq!(busy, path);
+                // Not every kernel exposes /proc/locks.
+                if let Some(pid) = pid {
+                    assert_eq!(pid, std::process::id());
+                }
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        // Closing the first file releases the lock.
+        drop(first);
+        lock_exclusive(&second, &path).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+/// Types that are valid for any bit pattern and have no padding, so they can
+/// be viewed in DMA memory.
+///
+/// # Safety
+/// Implementors must be `Copy`, have no padding bytes, and every bit pattern
+/// has to be a valid value of the type.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+// Complex is repr(C) with two fields of the same type.
+#[cfg(feature = "num-complex")]
+unsafe impl<T: Pod> Pod for num_complex::Complex<T> {}
@@ -1,5 +1,8 @@
 use std::ptr;
 
+use crate::DmaMemory;
+use crate::Error;
+
 const NXTDESC: isize = 0; // 0x0 / 4
 const NXTDESC_MSB: isize = 1; // 0x4 / 4
 const BUFFER_ADDRESS: isize = 0x8 / 4;
@@ -21,6 +24,20 @@ unsafe impl Sync for SgDescriptor {}
 // Descriptors are aligned to 16 words, even though only 8 or 8+5 words are used
 pub const SG_DESCRIPTOR_LEN: usize = 16 * 4;
 
+// The buffer length field has only 26 bits
+const MAX_BUFFER_LENGTH: usize = 0x3ff_ffff;
+
+/// Check that `len` fits the buffer length field of a descriptor.
+pub(crate) fn buffer_length(len: usize) -> Result<u32, Error> {
+    if len > MAX_BUFFER_LENGTH {
+        return Err(Error::TooLong {
+            len,
+            max: MAX_BUFFER_LENGTH,
+        });
+    }
+    Ok(len as u32)
+}
+
 impl SgDescriptor {
     /// # Safety
     /// Addresses point to mmaped DMA buffer that fits a SgDescriptor.
@@ -103,19 +120,38 @@ impl SgDescriptor {
         }
     }
 
+    /// Point the descriptor to `buffer`, setting buffer address and length.
+    ///
+    /// Fails with [`Error::TooLong`] if the buffer does not fit in the 26-bit
+    /// length field, leaving the descriptor untouched.
+    pub fn set_buffer<M: DmaMemory + ?Sized>(&mut self, buffer: &M) -> Result<(), Error> {
+        let length = buffer_length(buffer.len())?;
+        self.set_buffer_address(buffer.phys_addr());
+        self.set_buffer_length(length);
+        Ok(())
+    }
+
     pub fn buffer_length(&self) -> u32 {
         unsafe { ptr::read(self.base.offset(CONTROL)) & 0x3ffffff }
     }
 
+    /// Panics if `length` does not fit in the 26-bit length field, see
+    /// [`SgDescriptor::try_set_buffer_length`].
     pub fn set_buffer_length(&mut self, length: u32) {
-        // the buffer length field has only 26 bits
-        assert!(length <= 0x4000000);
+        assert!(length as usize <= MAX_BUFFER_LENGTH);
         unsafe {
             let ctrl = ptr::read(self.base.offset(CONTROL));
             ptr::write(self.base.offset(CONTROL), (ctrl & !0x3ffffff) | length);
         }
     }
 
+    /// Fails with [`Error::TooLong`] if `length` does not fit in the 26-bit
+    /// length field, leaving the descriptor untouched.
+    pub fn try_set_buffer_length(&mut self, length: u32) -> Result<(), Error> {
+        self.set_buffer_length(buffer_length(length as usize)?);
+        Ok(())
+    }
+
     pub fn eof(&self) -> bool {
         unsafe { ptr::read(self.base.offset(CONTROL)) & (1 << 26) != 0 }
     }
@@ -144,6 +180,11 @@ impl SgDescriptor {
         }
     }
 
+    /// The raw STATUS word.
+    pub fn status(&self) -> u32 {
+        unsafe { ptr::read_volatile(self.base.offset(STATUS)) }
+    }
+
     pub fn transferred_bytes(&self) -> u32 {
         unsafe { ptr::read_volatile(self.base.offset(STATUS)) & 0x3ffffff }
     }
@@ -188,3 +229,157 @@ impl SgDescriptor {
         }
     }
 }
+
+/// Descriptors chained into a circular list.
+///
+/// The ring keeps track of which descriptors have been handed to the DMA, so
+/// that completions can be reaped in order (see [`crate::AxiDma::completed_h2d`]
+/// and [`crate::AxiDma::completed_d2h`]).
+#[derive(Debug)]
+pub struct SgRing {
+    descriptors: Vec<SgDescriptor>,
+    submitted: Vec<bool>,
+    next_completion: usize,
+    // Descriptor skipped by a recovery, reported in place of its completion.
+    fault: Option<(usize, Error)>,
+}
+
+impl SgRing {
+    /// Chain `descriptors` into a ring, i.e., each descriptor points to the
+    /// next one and the last one points back to the first.
+    pub fn new(mut descriptors: Vec<SgDescriptor>) -> SgRing {
+        assert!(!descriptors.is_empty());
+        let n = descriptors.len();
+        for i in 0..n {
+            let next = descriptors[(i + 1) % n].phys_addr();
+            descriptors[i].set_next_descriptor(next);
+        }
+        SgRing {
+            submitted: vec![false; n],
+            descriptors,
+            next_completion: 0,
+            fault: None,
+        }
+    }
+
+    /// # Safety
+    /// Addresses point to mmaped DMA buffer that fits `count` consecutive
+    /// SgDescriptors.
+    pub unsafe fn from_base_ptr(base: *mut u32, phys_addr: usize, count: usize) -> SgRing {
+        let words = SG_DESCRIPTOR_LEN / std::mem::size_of::<u32>();
+        SgRing::new(
+            (0..count)
+                .map(|i| {
+                    SgDescriptor::from_base_ptr(
+                        base.add(i * words),
+                        phys_addr + i * SG_DESCRIPTOR_LEN,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+
+    pub fn descriptor(&self, index: usize) -> &SgDescriptor {
+        &self.descriptors[index]
+    }
+
+    pub fn descriptor_mut(&mut self, index: usize) -> &mut SgDescriptor {
+        &mut self.descriptors[index]
+    }
+
+    pub fn descriptors(&self) -> &[SgDescriptor] {
+        &self.descriptors
+    }
+
+    /// Index of the descriptor with the given physical address.
+    pub fn index_of(&self, phys_addr: usize) -> Option<usize> {
+        self.descriptors
+            .iter()
+            .position(|d| d.phys_addr() == phys_addr)
+    }
+
+    /// Whether the descriptor has been enqueued and not yet been reaped.
+    pub fn is_submitted(&self, index: usize) -> bool {
+        self.submitted[index]
+    }
+
+    /// Index of the descriptor that is expected to complete next.
+    pub fn next_completion(&self) -> usize {
+        self.next_completion
+    }
+
+    pub fn into_descriptors(self) -> Vec<SgDescriptor> {
+        self.descriptors
+    }
+
+    pub(crate) fn mark_submitted(&mut self, index: usize) {
+        self.submitted[index] = true;
+    }
+
+    pub(crate) fn set_fault(&mut self, index: usize, error: Error) {
+        self.fault = Some((index, error));
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn parts_mut(
+        &mut self,
+    ) -> (
+        &[SgDescriptor],
+        &mut [bool],
+        &mut usize,
+        &mut Option<(usize, Error)>,
+    ) {
+        (
+            &self.descriptors,
+            &mut self.submitted,
+            &mut self.next_completion,
+            &mut self.fault,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(64))]
+    struct Words([u32; 16]);
+
+    #[test]
+    fn buffer_length_limit() {
+        let mut words = Words([0; 16]);
+        let base = words.0.as_mut_ptr();
+        let mut descriptor = unsafe { SgDescriptor::from_base_ptr(base, base as usize) };
+        descriptor.set_eof(true);
+
+        descriptor.try_set_buffer_length(0x3ff_ffff).unwrap();
+        assert_eq!(descriptor.buffer_length(), 0x3ff_ffff);
+        assert!(matches!(
+            descriptor.try_set_buffer_length(0x400_0000),
+            Err(Error::TooLong {
+                len: 0x400_0000,
+                max: 0x3ff_ffff
+            })
+        ));
+        // Neither the length nor the flags next to it are touched.
+        assert_eq!(descriptor.buffer_length(), 0x3ff_ffff);
+        assert!(descriptor.eof());
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_buffer_length_too_long() {
+        let mut words = Words([0; 16]);
+        let base = words.0.as_mut_ptr();
+        let mut descriptor = unsafe { SgDescriptor::from_base_ptr(base, base as usize) };
+        descriptor.set_buffer_length(0x400_0000);
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Mount points of sysfs and the device nodes.
+///
+/// Defaults to `/sys` and `/dev`. Other roots are useful if sysfs is mounted
+/// elsewhere or to point the driver to fake attribute files in tests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SysRoot {
+    sys: PathBuf,
+    dev: PathBuf,
+}
+
+impl SysRoot {
+    pub fn new(sys: impl Into<PathBuf>, dev: impl Into<PathBuf>) -> SysRoot {
+        SysRoot {
+            sys: sys.into(),
+            dev: dev.into(),
+        }
+    }
+
+    pub fn sys(&self) -> &Path {
+        &self.sys
+    }
+
+    pub fn dev(&self) -> &Path {
+        &self.dev
+    }
+
+    pub(crate) fn u_dma_buf(&self, name: &str) -> PathBuf {
+        self.sys.join("class/u-dma-buf").join(name)
+    }
+
+    pub(crate) fn uio_class(&self) -> PathBuf {
+        self.sys.join("class/uio")
+    }
+
+    pub(crate) fn uio(&self, uio: &str) -> PathBuf {
+        self.uio_class().join(uio)
+    }
+
+    pub(crate) fn device(&self, name: &str) -> PathBuf {
+        self.dev.join(name)
+    }
+}
+
+impl Default for SysRoot {
+    fn default() -> SysRoot {
+        SysRoot::new("/sys", "/dev")
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::Error;
+use crate::SysRoot;
+
+/// UIO device, as listed in `/sys/class/uio`.
+///
+/// Only devices with a register map are listed. Address and size are the
+/// ones of `map0`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UioDevice {
+    uio: String,
+    name: String,
+    addr: usize,
+    size: usize,
+}
+
+impl UioDevice {
+    /// List all UIO devices under `root`, ordered by their index.
+    pub fn list(root: &SysRoot) -> Result<Vec<UioDevice>, Error> {
+        let entries = match fs::read_dir(root.uio_class()) {
+            Ok(entries) => entries,
+            // Without any UIO device, the class is not registered.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut devices = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let uio = entry.file_name().to_string_lossy().into_owned();
+            if let Some(device) = UioDevice::read(&entry.path(), uio)? {
+                devices.push(device);
+            }
+        }
+        devices.sort_by_key(|d| (d.index(), d.uio.clone()));
+        Ok(devices)
+    }
+
+    /// Find the first device whose device-tree name is `name`.
+    pub fn find_by_name(root: &SysRoot, name: &str) -> Result<UioDevice, Error> {
+        UioDevice::list(root)?
+            .into_iter()
+            .find(|d| d.name == name)
+            .ok_or_else(|| Error::NotFound(name.to_string()))
+    }
+
+    /// Find the device whose register map starts at physical address `addr`.
+    pub fn find_by_addr(root: &SysRoot, addr: usize) -> Result<UioDevice, Error> {
+        UioDevice::list(root)?
+            .into_iter()
+            .find(|d| d.addr == addr)
+            .ok_or_else(|| Error::NotFound(format!("0x{:x}", addr)))
+    }
+
+    /// Device node name, e.g., `uio4`, as passed to [`AxiDma::new`](crate::AxiDma::new).
+    pub fn uio(&self) -> &str {
+        &self.uio
+    }
+
+    /// Device-tree name of the device, e.g., `h2d-dma`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Physical address of the register map.
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Size of the register map in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn index(&self) -> usize {
+        self.uio
+            .strip_prefix("uio")
+            .and_then(|i| i.parse().ok())
+            .unwrap_or(usize::MAX)
+    }
+
+    fn read(sysfs: &Path, uio: String) -> Result<Option<UioDevice>, Error> {
+        let map = sysfs.join("maps/map0");
+        if !map.exists() {
+            return Ok(None);
+        }
+        Ok(Some(UioDevice {
+            uio,
+            name: read_attr(&sysfs.join("name"))?,
+            addr: read_hex(&map.join("addr"))?,
+            size: read_hex(&map.join("size"))?,
+        }))
+    }
+}
+
+fn read_attr(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+fn read_hex(path: &Path) -> Result<usize, Error> {
+    let value = read_attr(path)?;
+    Ok(usize::from_str_radix(value.trim_start_matches("0x"), 16)?)
+}
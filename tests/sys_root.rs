@@ -0,0 +1,292 @@
+//! Drive the crate with fake sysfs attributes and regular files standing in
+//! for the device nodes.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use xilinx_dma::{
+    AxiDma, AxiDmaOptions, DmaBufferOptions, Error, MappingMode, SyncDirection, SyncMode,
+    SyncOwner, SysRoot, UioDevice,
+};
+
+const BUFFER_SIZE: usize = 4096;
+
+/// Temporary directory with `sys` and `dev`, removed on drop.
+struct FakeRoot {
+    dir: PathBuf,
+}
+
+impl FakeRoot {
+    fn new(name: &str) -> FakeRoot {
+        let dir =
+            std::env::temp_dir().join(format!("xilinx-dma-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sys")).unwrap();
+        fs::create_dir_all(dir.join("dev")).unwrap();
+        FakeRoot { dir }
+    }
+
+    fn root(&self) -> SysRoot {
+        SysRoot::new(self.dir.join("sys"), self.dir.join("dev"))
+    }
+
+    /// Add a u-dma-buf with the given `sync_mode`.
+    fn add_buffer(&self, name: &str, sync_mode: &str) -> PathBuf {
+        let sysfs = self.dir.join("sys/class/u-dma-buf").join(name);
+        fs::create_dir_all(&sysfs).unwrap();
+        // Writes do not truncate the fake files, so the tests never replace a
+        // value with a shorter one.
+        for (attr, value) in [
+            ("phys_addr", "0x10000000"),
+            ("size", "4096"),
+            ("debug_vma", "0"),
+            ("sync_mode", sync_mode),
+            ("sync_offset", "0"),
+            ("sync_size", "0"),
+            ("sync_direction", "0"),
+            ("sync_owner", "0"),
+            ("sync_for_cpu", "0"),
+            ("sync_for_device", "0"),
+            ("dma_coherent", "0"),
+            ("driver_version", "4.8.0"),
+        ] {
+            fs::write(sysfs.join(attr), value).unwrap();
+        }
+        fs::write(self.dir.join("dev").join(name), vec![0u8; BUFFER_SIZE]).unwrap();
+        sysfs
+    }
+
+    /// Add a UIO device whose registers are a regular file.
+    fn add_uio(&self, uio: &str, name: &str, addr: usize) {
+        let sysfs = self.dir.join("sys/class/uio").join(uio);
+        let map = sysfs.join("maps/map0");
+        fs::create_dir_all(&map).unwrap();
+        fs::write(sysfs.join("name"), format!("{}\n", name)).unwrap();
+        fs::write(map.join("addr"), format!("0x{:08x}\n", addr)).unwrap();
+        fs::write(map.join("size"), "0x10000").unwrap();
+        fs::write(self.dir.join("dev").join(uio), vec![0u8; 0x10000]).unwrap();
+    }
+}
+
+impl Drop for FakeRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn attr(sysfs: &Path, attr: &str) -> String {
+    fs::read_to_string(sysfs.join(attr))
+        .unwrap()
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn dma_buffer_open() {
+    let fake = FakeRoot::new("buffer_open");
+    fake.add_buffer("udmabuf0", "1");
+    let mut buffer = DmaBufferOptions::new()
+        .root(fake.root())
+        .open("udmabuf0")
+        .unwrap();
+    assert_eq!(buffer.name(), "udmabuf0");
+    assert_eq!(buffer.size(), BUFFER_SIZE);
+    assert_eq!(buffer.phys_addr(), 0x1000_0000);
+
+    // The device file is mapped.
+    buffer.as_mut_slice::<u32>()[1] = 0x1234_5678;
+    drop(buffer);
+    let dev = fs::read(fake.dir.join("dev/udmabuf0")).unwrap();
+    assert_eq!(dev[4..8], 0x1234_5678u32.to_ne_bytes());
+}
+
+#[test]
+fn dma_buffer_missing() {
+    let fake = FakeRoot::new("buffer_missing");
+    let err = DmaBufferOptions::new()
+        .root(fake.root())
+        .open("udmabuf0")
+        .unwrap_err();
+    assert!(matches!(err, Error::Io(_)));
+}
+
+#[test]
+fn dma_buffer_attributes() {
+    let fake = FakeRoot::new("buffer_attributes");
+    let sysfs = fake.add_buffer("udmabuf0", "5");
+    let mut buffer = DmaBufferOptions::new()
+        .root(fake.root())
+        .open("udmabuf0")
+        .unwrap();
+    assert_eq!(buffer.sync_mode(), SyncMode::Uncached);
+    assert!(buffer.sync_mode_always());
+    assert!(!buffer.debug_vma());
+    assert_eq!(buffer.dma_coherent(), Some(false));
+    assert_eq!(buffer.driver_version(), Some("4.8.0"));
+    assert_eq!(buffer.sync_owner().unwrap(), SyncOwner::Cpu);
+
+    buffer.set_sync_mode(SyncMode::WriteCombine, false).unwrap();
+    assert_eq!(attr(&sysfs, "sync_mode"), "2");
+    assert_eq!(buffer.sync_mode(), SyncMode::WriteCombine);
+    assert!(!buffer.sync_mode_always());
+    assert!(buffer.set_sync_mode(SyncMode::Invalid, false).is_err());
+
+    buffer.set_debug_vma(true).unwrap();
+    assert_eq!(attr(&sysfs, "debug_vma"), "1");
+    assert!(buffer.debug_vma());
+}
+
+#[test]
+fn dma_buffer_sync_mode_without_cache_mode() {
+    let fake = FakeRoot::new("buffer_no_cache_mode");
+    fake.add_buffer("udmabuf0", "0");
+    fake.add_buffer("udmabuf1", "4");
+    let options = DmaBufferOptions::new().root(fake.root());
+
+    let buffer = options.open("udmabuf0").unwrap();
+    assert_eq!(buffer.sync_mode(), SyncMode::Invalid);
+    assert!(!buffer.sync_mode_always());
+
+    // The always flag without a cache mode keeps mappings cached.
+    let buffer = options.open("udmabuf1").unwrap();
+    assert_eq!(buffer.sync_mode(), SyncMode::Cached);
+    assert!(buffer.sync_mode_always());
+}
+
+#[test]
+fn dma_buffer_mapping_keeps_always() {
+    let fake = FakeRoot::new("buffer_mapping");
+    let sysfs = fake.add_buffer("udmabuf0", "5");
+    let buffer = DmaBufferOptions::new()
+        .root(fake.root())
+        .mapping(MappingMode::WriteCombine)
+        .open("udmabuf0")
+        .unwrap();
+    assert_eq!(attr(&sysfs, "sync_mode"), "6");
+    assert_eq!(buffer.sync_mode(), SyncMode::WriteCombine);
+    assert!(buffer.sync_mode_always());
+}
+
+#[test]
+fn dma_buffer_mapping_cached_clears_always() {
+    let fake = FakeRoot::new("buffer_mapping_cached");
+    let sysfs = fake.add_buffer("udmabuf0", "5");
+    let buffer = DmaBufferOptions::new()
+        .root(fake.root())
+        .mapping(MappingMode::Cached)
+        .open("udmabuf0")
+        .unwrap();
+    assert_eq!(attr(&sysfs, "sync_mode"), "1");
+    assert_eq!(buffer.sync_mode(), SyncMode::Uncached);
+    assert!(!buffer.sync_mode_always());
+}
+
+#[test]
+fn dma_buffer_sync_range() {
+    let fake = FakeRoot::new("buffer_sync_range");
+    let sysfs = fake.add_buffer("udmabuf0", "1");
+    let buffer = DmaBufferOptions::new()
+        .root(fake.root())
+        .open("udmabuf0")
+        .unwrap();
+
+    buffer
+        .sync_for_cpu_range(8, 1024, SyncDirection::FromDevice)
+        .unwrap();
+    assert_eq!(buffer.sync_offset().unwrap(), 8);
+    assert_eq!(buffer.sync_size().unwrap(), 1024);
+    assert_eq!(buffer.sync_direction().unwrap(), SyncDirection::FromDevice);
+    assert_eq!(attr(&sysfs, "sync_for_cpu"), "1");
+
+    // Whole-buffer syncs do not inherit the range of the last one.
+    buffer.sync_for_device().unwrap();
+    assert_eq!(buffer.sync_offset().unwrap(), 0);
+    assert_eq!(buffer.sync_size().unwrap(), BUFFER_SIZE);
+    assert_eq!(
+        buffer.sync_direction().unwrap(),
+        SyncDirection::Bidirectional
+    );
+    assert_eq!(attr(&sysfs, "sync_for_device"), "1");
+
+    let err = buffer
+        .sync_for_cpu_range(4000, 100, SyncDirection::FromDevice)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::OutOfBounds {
+            offset: 4000,
+            len: 100,
+            size: BUFFER_SIZE
+        }
+    ));
+    assert_eq!(buffer.sync_size().unwrap(), BUFFER_SIZE);
+}
+
+#[test]
+fn axi_dma_open() {
+    let fake = FakeRoot::new("axi_dma_open");
+    fake.add_uio("uio0", "dma", 0x4040_0000);
+    let dma = AxiDma::with_root("uio0", &fake.root()).unwrap();
+    assert_eq!(dma.size_d2h(), 0);
+}
+
+#[test]
+fn uio_list() {
+    let fake = FakeRoot::new("uio_list");
+    fake.add_uio("uio10", "d2h-dma", 0x4041_0000);
+    fake.add_uio("uio2", "h2d-dma", 0x4040_0000);
+    // Devices without a register map are skipped.
+    fs::create_dir_all(fake.dir.join("sys/class/uio/uio3")).unwrap();
+
+    let devices = UioDevice::list(&fake.root()).unwrap();
+    let uios: Vec<_> = devices.iter().map(|d| d.uio()).collect();
+    assert_eq!(uios, ["uio2", "uio10"]);
+    assert_eq!(devices[0].name(), "h2d-dma");
+    assert_eq!(devices[0].addr(), 0x4040_0000);
+    assert_eq!(devices[0].size(), 0x10000);
+}
+
+#[test]
+fn uio_list_without_class() {
+    let fake = FakeRoot::new("uio_no_class");
+    assert!(UioDevice::list(&fake.root()).unwrap().is_empty());
+    let err = UioDevice::find_by_name(&fake.root(), "h2d-dma").unwrap_err();
+    assert!(matches!(err, Error::NotFound(_)));
+}
+
+#[test]
+fn uio_find() {
+    let fake = FakeRoot::new("uio_find");
+    fake.add_uio("uio0", "h2d-dma", 0x4040_0000);
+    fake.add_uio("uio1", "d2h-dma", 0x4041_0000);
+    let root = fake.root();
+
+    assert_eq!(
+        UioDevice::find_by_name(&root, "d2h-dma").unwrap().uio(),
+        "uio1"
+    );
+    assert_eq!(
+        UioDevice::find_by_addr(&root, 0x4040_0000).unwrap().uio(),
+        "uio0"
+    );
+    let err = UioDevice::find_by_name(&root, "other").unwrap_err();
+    assert!(matches!(err, Error::NotFound(name) if name == "other"));
+    let err = UioDevice::find_by_addr(&root, 0x4042_0000).unwrap_err();
+    assert!(matches!(err, Error::NotFound(addr) if addr == "0x40420000"));
+}
+
+#[test]
+fn axi_dma_options_find() {
+    let fake = FakeRoot::new("axi_dma_find");
+    fake.add_uio("uio0", "h2d-dma", 0x4040_0000);
+    let options = AxiDmaOptions::new().root(fake.root());
+    assert_eq!(options.list_devices().unwrap().len(), 1);
+
+    let device = options.find_by_addr(0x4040_0000).unwrap();
+    let _dma = options.open(device.uio()).unwrap();
+    // The lock is taken by the first handle.
+    let device = options.find_by_name("h2d-dma").unwrap();
+    let err = options.open(device.uio()).unwrap_err();
+    assert!(matches!(err, Error::Busy { .. }));
+    options.clone().lock(false).open(device.uio()).unwrap();
+}